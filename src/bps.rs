@@ -0,0 +1,260 @@
+//! Beat patch format (BPS). A BPS patch describes how to build the
+//! target file out of the source file, as a sequence of actions:
+//! - SourceRead: copy bytes from the source at the current position
+//! - TargetRead: copy bytes stored in the patch
+//! - SourceCopy: copy bytes from anywhere in the source
+//! - TargetCopy: copy bytes from earlier in the target
+//!
+//! The patch starts with the signature `BPS1`, the sizes of the source
+//! and target files and a block of metadata. It ends with the CRC-32 of
+//! the source, of the target and of the patch itself, so a patch can't
+//! be applied to the wrong file and a damaged patch is detected.
+//!
+//! All numbers in the patch are variable length, 7 bits per byte with
+//! the last byte marked by the top bit. Each byte after the first
+//! also implicitly adds one to the number, so every number has exactly
+//! one encoding.
+//!
+//! We only create patches with SourceRead and TargetRead actions,
+//! because a mod changes bytes in place. All four actions are supported
+//! when applying a patch.
+
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+
+use crate::overlay::Overlay;
+
+pub const SIGNATURE: &[u8] = b"BPS1";
+
+const FOOTER_SIZE_BYTES: usize = 12;
+
+const SOURCE_READ: u64 = 0;
+const TARGET_READ: u64 = 1;
+const SOURCE_COPY: u64 = 2;
+const TARGET_COPY: u64 = 3;
+
+const CRC_32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+fn encode_number(bps: &mut Vec<u8>, mut number: u64) {
+    loop {
+        let low_bits = (number & 0x7F) as u8;
+        number >>= 7;
+        if number == 0 {
+            bps.push(0x80 | low_bits);
+            return;
+        }
+        bps.push(low_bits);
+        number -= 1;
+    }
+}
+
+fn decode_number(bps: &[u8], offset: &mut usize) -> u64 {
+    let mut number = 0;
+    let mut shift = 1;
+    loop {
+        let byte = bps[*offset];
+        *offset += 1;
+        number += (byte & 0x7F) as u64 * shift;
+        if byte & 0x80 != 0 {
+            return number;
+        }
+        shift <<= 7;
+        number += shift;
+    }
+}
+
+/// Calculate the CRC-32 of everything `reader` returns, from its
+/// current position to its end.
+fn calculate_crc<R: Read>(reader: &mut R) -> u32 {
+    let mut digest = CRC_32.digest();
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let size = reader.read(&mut buffer).unwrap();
+        if size == 0 {
+            return digest.finalize();
+        }
+        digest.update(&buffer[..size]);
+    }
+}
+
+/// Create a patch from the file underlying `overlay` to the file with
+/// every write made through `overlay`.
+pub fn create_patch<F: Read + Seek>(overlay: &mut Overlay<F>) -> Vec<u8> {
+    let source_size = overlay.base_size_bytes();
+    let target_size = overlay.size_bytes();
+
+    let mut bps = SIGNATURE.to_vec();
+    encode_number(&mut bps, source_size);
+    encode_number(&mut bps, target_size);
+    encode_number(&mut bps, 0);
+
+    let mut output_offset = 0;
+    for change in overlay.changes() {
+        if change.offset > output_offset {
+            let length = change.offset - output_offset;
+            encode_number(&mut bps, (length - 1) << 2 | SOURCE_READ);
+        }
+        encode_number(
+            &mut bps,
+            (change.data.len() as u64 - 1) << 2 | TARGET_READ,
+        );
+        bps.extend_from_slice(&change.data);
+        output_offset = change.end();
+    }
+    if output_offset < target_size {
+        let length = target_size - output_offset;
+        encode_number(&mut bps, (length - 1) << 2 | SOURCE_READ);
+    }
+
+    overlay.seek(SeekFrom::Start(0)).unwrap();
+    let target_crc = calculate_crc(overlay);
+    let mut source = vec![0; 1 << 20];
+    let mut source_digest = CRC_32.digest();
+    let mut offset = 0;
+    while offset < source_size {
+        let size = (source_size - offset).min(source.len() as u64) as usize;
+        overlay.read_base(offset, &mut source[..size]);
+        source_digest.update(&source[..size]);
+        offset += size as u64;
+    }
+
+    bps.extend_from_slice(&source_digest.finalize().to_le_bytes());
+    bps.extend_from_slice(&target_crc.to_le_bytes());
+    let patch_crc = CRC_32.checksum(&bps);
+    bps.extend_from_slice(&patch_crc.to_le_bytes());
+
+    return bps;
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> u32 {
+    return u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+}
+
+/// Copy `length` bytes from `offset` of `reader` to `writer`.
+fn copy_range<R: Read + Seek, W: Write>(
+    reader: &mut R,
+    offset: u64,
+    length: u64,
+    writer: &mut W,
+) {
+    reader.seek(SeekFrom::Start(offset)).unwrap();
+    let copied = std::io::copy(&mut reader.take(length), writer).unwrap();
+    assert!(copied == length, "The patch reads past the end of a file");
+}
+
+/// Apply a relative offset, stored with the sign in the lowest bit.
+fn apply_relative_offset(offset: u64, encoded: u64) -> u64 {
+    let distance = encoded >> 1;
+    if encoded & 1 != 0 {
+        return offset.checked_sub(distance).unwrap();
+    }
+    return offset + distance;
+}
+
+/// Apply a patch to `source`, writing the result to `target`, which
+/// should be empty.
+///
+/// Panics if the patch is damaged, or if the source or the result don't
+/// match the checksums in the patch. In the last case `target` has
+/// already been written.
+pub fn apply_patch<S: Read + Seek, T: Read + Write + Seek>(
+    source: &mut S,
+    target: &mut T,
+    bps: &[u8],
+) {
+    assert!(bps.starts_with(SIGNATURE), "Not a BPS patch");
+    assert!(bps.len() >= SIGNATURE.len() + FOOTER_SIZE_BYTES);
+
+    let footer_offset = bps.len() - FOOTER_SIZE_BYTES;
+    let source_crc = read_u32_le(bps, footer_offset);
+    let target_crc = read_u32_le(bps, footer_offset + 4);
+    let patch_crc = read_u32_le(bps, footer_offset + 8);
+    assert!(
+        CRC_32.checksum(&bps[..footer_offset + 8]) == patch_crc,
+        "The patch is damaged"
+    );
+
+    let mut offset = SIGNATURE.len();
+    let source_size = decode_number(bps, &mut offset);
+    let target_size = decode_number(bps, &mut offset);
+    let metadata_size = decode_number(bps, &mut offset);
+    offset += metadata_size as usize;
+
+    assert!(
+        source.seek(SeekFrom::End(0)).unwrap() == source_size,
+        "The patch was made for a file of a different size"
+    );
+    source.seek(SeekFrom::Start(0)).unwrap();
+    assert!(
+        calculate_crc(source) == source_crc,
+        "The patch was made for a different file"
+    );
+
+    let mut writer = BufWriter::new(target);
+    let mut output_offset = 0;
+    let mut source_relative_offset = 0;
+    let mut target_relative_offset = 0;
+
+    while offset < footer_offset {
+        let action = decode_number(bps, &mut offset);
+        let length = (action >> 2) + 1;
+
+        match action & 3 {
+            SOURCE_READ => {
+                copy_range(source, output_offset, length, &mut writer);
+            }
+            TARGET_READ => {
+                let end = offset + length as usize;
+                writer.write_all(&bps[offset..end]).unwrap();
+                offset = end;
+            }
+            SOURCE_COPY => {
+                let encoded = decode_number(bps, &mut offset);
+                source_relative_offset =
+                    apply_relative_offset(source_relative_offset, encoded);
+                copy_range(source, source_relative_offset, length, &mut writer);
+                source_relative_offset += length;
+            }
+            TARGET_COPY => {
+                let encoded = decode_number(bps, &mut offset);
+                target_relative_offset =
+                    apply_relative_offset(target_relative_offset, encoded);
+                assert!(target_relative_offset < output_offset);
+
+                // The copy may overlap the bytes it produces, eg to
+                // repeat a pattern, so it is done in pieces that only
+                // read what has already been written.
+                let mut remaining = length;
+                while remaining > 0 {
+                    let size =
+                        remaining.min(output_offset - target_relative_offset);
+                    writer.flush().unwrap();
+                    let target = writer.get_mut();
+                    let mut piece = vec![0; size as usize];
+                    target
+                        .seek(SeekFrom::Start(target_relative_offset))
+                        .unwrap();
+                    target.read_exact(&mut piece).unwrap();
+                    target.seek(SeekFrom::Start(output_offset)).unwrap();
+                    target.write_all(&piece).unwrap();
+
+                    target_relative_offset += size;
+                    output_offset += size;
+                    remaining -= size;
+                }
+                continue;
+            }
+            _ => unreachable!(),
+        }
+
+        output_offset += length;
+    }
+    assert!(offset == footer_offset, "The patch is damaged");
+    assert!(output_offset == target_size, "The patch is damaged");
+
+    let target = writer.into_inner().ok().unwrap();
+    target.seek(SeekFrom::Start(0)).unwrap();
+    assert!(
+        calculate_crc(target) == target_crc,
+        "The patched file doesn't match the patch"
+    );
+}
@@ -0,0 +1,939 @@
+//! Module to manipulate card data. The function names follow the same
+//! convention as the duelist module:
+//! - read/write if they operate on the ROM file
+//! - load/dump if they operate on csv files
+//!
+//! The stats of each card are packed in a 4 byte little-endian integer
+//! in the SLUS file, in the table at `CARD_STATS_OFFSET`:
+//! - bits 0-8: ATK / 10
+//! - bits 9-17: DEF / 10
+//! - bits 18-21: second guardian star
+//! - bits 22-25: first guardian star
+//! - bits 26-30: type
+//!
+//! The level and attribute are stored separately, one byte per card in
+//! the table at `CARD_LEVEL_ATTRIBUTE_OFFSET`, with the level in the
+//! low 4 bits and the attribute in the high 4 bits.
+//!
+//! The starchip cost and password of each card are in the wa_mrg file
+//! instead, 8 bytes per card in the table at `STARCHIPS_OFFSET`: the
+//! cost as a 4 byte little-endian integer, followed by the 8 digits of
+//! the password in BCD, also little-endian. Cards that can't be bought
+//! with a password have bytes that aren't valid BCD instead.
+//!
+//! The descriptions are in a pool of text in the slus file, see the
+//! `text_pool` module. Each card has a 2 byte little-endian pointer to
+//! its description, relative to `DESCRIPTION_OFFSET`, in the table at
+//! `DESCRIPTION_INDICES_OFFSET`. The base of the pointers comes from
+//! fmscrambler, and I haven't been able to check it against a retail
+//! dump yet. If it is wrong, the dumped descriptions are garbage, and
+//! laying them out again would overwrite whatever is at the real
+//! offset. So `apply` leaves the descriptions alone until the base is
+//! verified, see `write_all_descriptions`.
+
+use csv::{ReaderBuilder, Writer};
+
+use crate::duelist::NUMBER_OF_CARDS;
+use crate::image::{SlusExecutable, WaMrgArchive};
+use crate::text::{self, Token};
+use crate::text_pool::{LayoutError, PointerTable, TextPool};
+
+pub const CARD_STATS_OFFSET: usize = 0x1C4A44;
+pub const CARD_LEVEL_ATTRIBUTE_OFFSET: usize = 0x1C5B33;
+
+/// ATK and DEF are stored divided by 10 in 9 bits.
+const STAT_MULTIPLIER: u16 = 10;
+pub const MAX_STAT: u16 = 0x1FF * STAT_MULTIPLIER;
+
+/// Bits of the packed stats that hold the stats. The highest bit is
+/// left as it is when writing.
+const STATS_MASK: u32 = 0x7FFFFFFF;
+
+/// Levels are stored in 4 bits, but the game only uses up to 12.
+pub const MAX_LEVEL: u8 = 12;
+
+pub const STARCHIPS_OFFSET: usize = 0xFB9808;
+pub const STARCHIPS_SIZE: usize = 8;
+
+/// The password shop shows up to 6 digits of starchips.
+pub const MAX_STARCHIP_COST: u32 = 999_999;
+pub const MAX_PASSWORD: u32 = 99_999_999;
+
+pub const DESCRIPTION_INDICES_OFFSET: usize = 0x1B0A02;
+
+/// The offset that fmscrambler reads the description pointers relative
+/// to, before subtracting `0x9F4` from them.
+const DESCRIPTION_BLOCK_OFFSET: usize = 0x1B0800;
+
+/// The base of the description pointers, as fmscrambler reads them.
+/// Unverified, see the module documentation.
+pub const DESCRIPTION_OFFSET: usize = DESCRIPTION_BLOCK_OFFSET - 0x9F4;
+
+/// The pool of descriptions. The space from `DESCRIPTION_BLOCK_OFFSET`
+/// to the description table might be the start of a larger pointer
+/// table, whose other strings would share the pool. We don't know what
+/// they are, so laying out the descriptions fails if any of those slots
+/// points into them.
+pub const DESCRIPTION_POOL: TextPool = TextPool {
+    name: "descriptions",
+    base_offset: DESCRIPTION_OFFSET,
+    pointer_tables: &[PointerTable {
+        offset: DESCRIPTION_INDICES_OFFSET,
+        count: NUMBER_OF_CARDS,
+    }],
+    unknown_pointers: &[PointerTable {
+        offset: DESCRIPTION_BLOCK_OFFSET,
+        count: (DESCRIPTION_INDICES_OFFSET - DESCRIPTION_BLOCK_OFFSET) / 2,
+    }],
+};
+
+/// The password bytes of cards without a password, according to
+/// fmscrambler. Any other value that isn't valid BCD is read as no
+/// password as well. To clear a password, we copy the bytes of a card
+/// that has none in the file, see `no_password_bytes`, and only fall
+/// back to this if there is no such card.
+const NO_PASSWORD: u32 = 0xFFFFFFFE;
+
+/// The type of a card. The last four are the types of the non-monster
+/// cards, see `CardKind`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CardType {
+    Dragon,
+    Spellcaster,
+    Zombie,
+    Warrior,
+    BeastWarrior,
+    Beast,
+    WingedBeast,
+    Fiend,
+    Fairy,
+    Insect,
+    Dinosaur,
+    Reptile,
+    Fish,
+    SeaSerpent,
+    Machine,
+    Thunder,
+    Aqua,
+    Pyro,
+    Rock,
+    Plant,
+    Magic,
+    Trap,
+    Ritual,
+    Equip,
+}
+
+impl CardType {
+    /// Every type, in the order of the values the game uses for them.
+    pub const ALL: [CardType; 24] = [
+        CardType::Dragon,
+        CardType::Spellcaster,
+        CardType::Zombie,
+        CardType::Warrior,
+        CardType::BeastWarrior,
+        CardType::Beast,
+        CardType::WingedBeast,
+        CardType::Fiend,
+        CardType::Fairy,
+        CardType::Insect,
+        CardType::Dinosaur,
+        CardType::Reptile,
+        CardType::Fish,
+        CardType::SeaSerpent,
+        CardType::Machine,
+        CardType::Thunder,
+        CardType::Aqua,
+        CardType::Pyro,
+        CardType::Rock,
+        CardType::Plant,
+        CardType::Magic,
+        CardType::Trap,
+        CardType::Ritual,
+        CardType::Equip,
+    ];
+
+    pub fn from_id(id: u8) -> Option<CardType> {
+        return CardType::ALL.get(id as usize).copied();
+    }
+
+    pub fn id(self) -> u8 {
+        return self as u8;
+    }
+
+    /// Parse the name returned by `name`.
+    pub fn from_name(name: &str) -> Option<CardType> {
+        return CardType::ALL.into_iter().find(|value| value.name() == name);
+    }
+
+    pub fn name(self) -> &'static str {
+        return match self {
+            CardType::Dragon => "Dragon",
+            CardType::Spellcaster => "Spellcaster",
+            CardType::Zombie => "Zombie",
+            CardType::Warrior => "Warrior",
+            CardType::BeastWarrior => "Beast-Warrior",
+            CardType::Beast => "Beast",
+            CardType::WingedBeast => "Winged Beast",
+            CardType::Fiend => "Fiend",
+            CardType::Fairy => "Fairy",
+            CardType::Insect => "Insect",
+            CardType::Dinosaur => "Dinosaur",
+            CardType::Reptile => "Reptile",
+            CardType::Fish => "Fish",
+            CardType::SeaSerpent => "Sea Serpent",
+            CardType::Machine => "Machine",
+            CardType::Thunder => "Thunder",
+            CardType::Aqua => "Aqua",
+            CardType::Pyro => "Pyro",
+            CardType::Rock => "Rock",
+            CardType::Plant => "Plant",
+            CardType::Magic => "Magic",
+            CardType::Trap => "Trap",
+            CardType::Ritual => "Ritual",
+            CardType::Equip => "Equip",
+        };
+    }
+}
+
+/// The kind of a card, which decides how it is played. It follows from
+/// the type of the card.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CardKind {
+    Monster,
+    Magic,
+    Trap,
+    Equip,
+    Ritual,
+}
+
+impl CardKind {
+    pub fn name(self) -> &'static str {
+        return match self {
+            CardKind::Monster => "Monster",
+            CardKind::Magic => "Magic",
+            CardKind::Trap => "Trap",
+            CardKind::Equip => "Equip",
+            CardKind::Ritual => "Ritual",
+        };
+    }
+}
+
+/// A guardian star. Cards that aren't monsters have none.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GuardianStar {
+    None,
+    Mars,
+    Jupiter,
+    Saturn,
+    Uranus,
+    Pluto,
+    Neptune,
+    Mercury,
+    Sun,
+    Moon,
+    Venus,
+}
+
+impl GuardianStar {
+    /// Every guardian star, in the order of the values the game uses
+    /// for them.
+    pub const ALL: [GuardianStar; 11] = [
+        GuardianStar::None,
+        GuardianStar::Mars,
+        GuardianStar::Jupiter,
+        GuardianStar::Saturn,
+        GuardianStar::Uranus,
+        GuardianStar::Pluto,
+        GuardianStar::Neptune,
+        GuardianStar::Mercury,
+        GuardianStar::Sun,
+        GuardianStar::Moon,
+        GuardianStar::Venus,
+    ];
+
+    pub fn from_id(id: u8) -> Option<GuardianStar> {
+        return GuardianStar::ALL.get(id as usize).copied();
+    }
+
+    pub fn id(self) -> u8 {
+        return self as u8;
+    }
+
+    /// Parse the name returned by `name`.
+    pub fn from_name(name: &str) -> Option<GuardianStar> {
+        return GuardianStar::ALL
+            .into_iter()
+            .find(|value| value.name() == name);
+    }
+
+    pub fn name(self) -> &'static str {
+        return match self {
+            GuardianStar::None => "None",
+            GuardianStar::Mars => "Mars",
+            GuardianStar::Jupiter => "Jupiter",
+            GuardianStar::Saturn => "Saturn",
+            GuardianStar::Uranus => "Uranus",
+            GuardianStar::Pluto => "Pluto",
+            GuardianStar::Neptune => "Neptune",
+            GuardianStar::Mercury => "Mercury",
+            GuardianStar::Sun => "Sun",
+            GuardianStar::Moon => "Moon",
+            GuardianStar::Venus => "Venus",
+        };
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Attribute {
+    Light,
+    Dark,
+    Earth,
+    Water,
+    Fire,
+    Wind,
+    Magic,
+    Trap,
+}
+
+impl Attribute {
+    /// Every attribute, in the order of the values the game uses for
+    /// them.
+    pub const ALL: [Attribute; 8] = [
+        Attribute::Light,
+        Attribute::Dark,
+        Attribute::Earth,
+        Attribute::Water,
+        Attribute::Fire,
+        Attribute::Wind,
+        Attribute::Magic,
+        Attribute::Trap,
+    ];
+
+    pub fn from_id(id: u8) -> Option<Attribute> {
+        return Attribute::ALL.get(id as usize).copied();
+    }
+
+    pub fn id(self) -> u8 {
+        return self as u8;
+    }
+
+    /// Parse the name returned by `name`.
+    pub fn from_name(name: &str) -> Option<Attribute> {
+        return Attribute::ALL
+            .into_iter()
+            .find(|value| value.name() == name);
+    }
+
+    pub fn name(self) -> &'static str {
+        return match self {
+            Attribute::Light => "Light",
+            Attribute::Dark => "Dark",
+            Attribute::Earth => "Earth",
+            Attribute::Water => "Water",
+            Attribute::Fire => "Fire",
+            Attribute::Wind => "Wind",
+            Attribute::Magic => "Magic",
+            Attribute::Trap => "Trap",
+        };
+    }
+}
+
+/// The stats of a single card.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Card {
+    pub attack: u16,
+    pub defense: u16,
+    pub guardian_star_1: GuardianStar,
+    pub guardian_star_2: GuardianStar,
+    pub card_type: CardType,
+    pub attribute: Attribute,
+    pub level: u8,
+    pub starchip_cost: u32,
+
+    /// The password that buys the card, if it has one. Passwords are 8
+    /// digits, leading zeros included.
+    pub password: Option<u32>,
+
+    /// The text shown in the card viewer, with its control codes.
+    pub description: Vec<Token>,
+}
+
+impl Card {
+    pub fn kind(&self) -> CardKind {
+        return match self.card_type {
+            CardType::Magic => CardKind::Magic,
+            CardType::Trap => CardKind::Trap,
+            CardType::Ritual => CardKind::Ritual,
+            CardType::Equip => CardKind::Equip,
+            _ => CardKind::Monster,
+        };
+    }
+}
+
+/// Decode an 8 digit BCD number, or return None if any of its digits
+/// isn't a decimal digit.
+fn decode_bcd(bcd: u32) -> Option<u32> {
+    let mut value = 0;
+    for shift in (0..32).step_by(4).rev() {
+        let digit = (bcd >> shift) & 0xF;
+        if digit > 9 {
+            return None;
+        }
+        value = value * 10 + digit;
+    }
+    return Some(value);
+}
+
+fn encode_bcd(mut value: u32) -> u32 {
+    assert!(value <= MAX_PASSWORD);
+    let mut bcd = 0;
+    for shift in (0..32).step_by(4) {
+        bcd |= (value % 10) << shift;
+        value /= 10;
+    }
+    return bcd;
+}
+
+/// Read a single card's stats. The starchip cost, password and
+/// description are left empty and filled in by `read_all_cards`.
+fn read_card(slus: &[u8], card_id: usize) -> Card {
+    let stats_offset = CARD_STATS_OFFSET + 4 * card_id;
+    let stats = u32::from_le_bytes(
+        slus[stats_offset..stats_offset + 4].try_into().unwrap(),
+    );
+    let level_attribute = slus[CARD_LEVEL_ATTRIBUTE_OFFSET + card_id];
+
+    let guardian_star_1 = ((stats >> 22) & 0xF) as u8;
+    let guardian_star_2 = ((stats >> 18) & 0xF) as u8;
+    let card_type = ((stats >> 26) & 0x1F) as u8;
+    let attribute = level_attribute >> 4;
+
+    // The official numbers start at 1.
+    let card_number = card_id + 1;
+    return Card {
+        attack: (stats & 0x1FF) as u16 * STAT_MULTIPLIER,
+        defense: ((stats >> 9) & 0x1FF) as u16 * STAT_MULTIPLIER,
+        guardian_star_1: GuardianStar::from_id(guardian_star_1).unwrap_or_else(
+            || {
+                panic!(
+                    "Card {card_number} has unknown guardian star \
+                     {guardian_star_1}"
+                )
+            },
+        ),
+        guardian_star_2: GuardianStar::from_id(guardian_star_2).unwrap_or_else(
+            || {
+                panic!(
+                    "Card {card_number} has unknown guardian star \
+                     {guardian_star_2}"
+                )
+            },
+        ),
+        card_type: CardType::from_id(card_type).unwrap_or_else(|| {
+            panic!("Card {card_number} has unknown type {card_type}")
+        }),
+        attribute: Attribute::from_id(attribute).unwrap_or_else(|| {
+            panic!("Card {card_number} has unknown attribute {attribute}")
+        }),
+        level: level_attribute & 0xF,
+        starchip_cost: 0,
+        password: None,
+        description: Vec::new(),
+    };
+}
+
+/// Read a single card's starchip cost and password.
+fn read_starchips(wa_mrg: &[u8], card_id: usize) -> (u32, Option<u32>) {
+    let offset = STARCHIPS_OFFSET + STARCHIPS_SIZE * card_id;
+    let starchip_cost =
+        u32::from_le_bytes(wa_mrg[offset..offset + 4].try_into().unwrap());
+    let password =
+        u32::from_le_bytes(wa_mrg[offset + 4..offset + 8].try_into().unwrap());
+    return (starchip_cost, decode_bcd(password));
+}
+
+/// Write a single card's stats into the given slus file.
+fn write_card(slus: &mut [u8], card_id: usize, card: &Card) {
+    let card_number = card_id + 1;
+    assert!(
+        card.attack <= MAX_STAT && card.attack.is_multiple_of(STAT_MULTIPLIER),
+        "Card {card_number} has invalid ATK {}, it must be a multiple of \
+         {STAT_MULTIPLIER} up to {MAX_STAT}",
+        card.attack
+    );
+    assert!(
+        card.defense <= MAX_STAT
+            && card.defense.is_multiple_of(STAT_MULTIPLIER),
+        "Card {card_number} has invalid DEF {}, it must be a multiple of \
+         {STAT_MULTIPLIER} up to {MAX_STAT}",
+        card.defense
+    );
+    assert!(
+        card.level <= MAX_LEVEL,
+        "Card {card_number} has invalid level {}, it must be up to \
+         {MAX_LEVEL}",
+        card.level
+    );
+
+    let stats_offset = CARD_STATS_OFFSET + 4 * card_id;
+    let old_stats = u32::from_le_bytes(
+        slus[stats_offset..stats_offset + 4].try_into().unwrap(),
+    );
+    let stats = (old_stats & !STATS_MASK)
+        | (card.attack / STAT_MULTIPLIER) as u32
+        | ((card.defense / STAT_MULTIPLIER) as u32) << 9
+        | (card.guardian_star_2.id() as u32) << 18
+        | (card.guardian_star_1.id() as u32) << 22
+        | (card.card_type.id() as u32) << 26;
+
+    slus[stats_offset..stats_offset + 4].copy_from_slice(&stats.to_le_bytes());
+    slus[CARD_LEVEL_ATTRIBUTE_OFFSET + card_id] =
+        card.attribute.id() << 4 | card.level;
+}
+
+/// Return the password bytes of the first card in the given wa_mrg file
+/// that has no password, so that clearing a password writes a value
+/// that the game already uses, or `NO_PASSWORD` if every card has one.
+fn no_password_bytes(wa_mrg: &[u8]) -> u32 {
+    for card_id in 0..NUMBER_OF_CARDS {
+        let offset = STARCHIPS_OFFSET + STARCHIPS_SIZE * card_id + 4;
+        let password =
+            u32::from_le_bytes(wa_mrg[offset..offset + 4].try_into().unwrap());
+        if decode_bcd(password).is_none() {
+            return password;
+        }
+    }
+    return NO_PASSWORD;
+}
+
+/// Write a single card's starchip cost and password into the given
+/// wa_mrg file. The password is only written if it changed, so that
+/// cards without one keep their original bytes.
+fn write_starchips(wa_mrg: &mut [u8], card_id: usize, card: &Card) {
+    let card_number = card_id + 1;
+    assert!(
+        card.starchip_cost <= MAX_STARCHIP_COST,
+        "Card {card_number} has invalid starchip cost {}, it must be up to \
+         {MAX_STARCHIP_COST}",
+        card.starchip_cost
+    );
+    if let Some(password) = card.password {
+        assert!(
+            password <= MAX_PASSWORD,
+            "Card {card_number} has invalid password {password}, it must \
+             have at most 8 digits"
+        );
+    }
+
+    let offset = STARCHIPS_OFFSET + STARCHIPS_SIZE * card_id;
+    wa_mrg[offset..offset + 4]
+        .copy_from_slice(&card.starchip_cost.to_le_bytes());
+
+    let (_, old_password) = read_starchips(wa_mrg, card_id);
+    if card.password != old_password {
+        let password = match card.password {
+            Some(password) => encode_bcd(password),
+            None => no_password_bytes(wa_mrg),
+        };
+        wa_mrg[offset + 4..offset + 8].copy_from_slice(&password.to_le_bytes());
+    }
+}
+
+/// Read the stats of all the cards from the given slus and wa_mrg
+/// files.
+pub fn read_all_cards(
+    slus: &SlusExecutable,
+    wa_mrg: &WaMrgArchive,
+) -> Vec<Card> {
+    let slus = slus.as_bytes();
+    let wa_mrg = wa_mrg.as_bytes();
+    let descriptions = DESCRIPTION_POOL.read_strings(slus).pop().unwrap();
+    let mut cards = Vec::new();
+
+    for (card_id, description) in descriptions.into_iter().enumerate() {
+        let mut card = read_card(slus, card_id);
+        (card.starchip_cost, card.password) = read_starchips(wa_mrg, card_id);
+        card.description = text::decode_tokens(&description);
+        cards.push(card);
+    }
+
+    return cards;
+}
+
+/// Write the stats of all the cards into the given slus and wa_mrg
+/// files. The descriptions are left alone, see
+/// `write_all_descriptions`.
+pub fn write_all_cards(
+    slus: &mut SlusExecutable,
+    wa_mrg: &mut WaMrgArchive,
+    cards: &[Card],
+) {
+    assert!(cards.len() == NUMBER_OF_CARDS);
+
+    let slus = slus.as_bytes_mut();
+    let wa_mrg = wa_mrg.as_bytes_mut();
+    for (card_id, card) in cards.iter().enumerate() {
+        write_card(slus, card_id, card);
+        write_starchips(wa_mrg, card_id, card);
+    }
+}
+
+/// Write the descriptions of all the cards into the given slus file.
+/// Return the number of bytes left free in the description pool.
+///
+/// The descriptions are only laid out again if any of them changed, so
+/// that the original layout is kept when there is nothing to change.
+/// Fails if they don't fit in the pool, before writing anything.
+///
+/// `apply` doesn't call this until `DESCRIPTION_OFFSET` is verified,
+/// see the module documentation.
+pub fn write_all_descriptions(
+    slus: &mut SlusExecutable,
+    cards: &[Card],
+) -> Result<usize, LayoutError> {
+    assert!(cards.len() == NUMBER_OF_CARDS);
+
+    let slus = slus.as_bytes_mut();
+    let original_descriptions =
+        DESCRIPTION_POOL.read_strings(slus).pop().unwrap();
+    let mut descriptions = Vec::new();
+    for (card_id, card) in cards.iter().enumerate() {
+        descriptions.push(
+            text::encode_tokens(&card.description).unwrap_or_else(|error| {
+                panic!("Invalid description for card {}: {error}", card_id + 1)
+            }),
+        );
+    }
+
+    if descriptions == original_descriptions {
+        return DESCRIPTION_POOL.free_bytes(slus);
+    }
+    return DESCRIPTION_POOL.write_strings(slus, &[descriptions]);
+}
+
+/// Format a password with all 8 digits, or as `none` if the card has
+/// none.
+fn format_password(password: Option<u32>) -> String {
+    return match password {
+        Some(password) => format!("{password:08}"),
+        None => String::from("none"),
+    };
+}
+
+/// Parse a password written by `format_password`.
+fn parse_password(field: &str) -> Option<Option<u32>> {
+    if field == "none" {
+        return Some(None);
+    }
+    if field.len() != 8 || !field.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    return Some(Some(field.parse().unwrap()));
+}
+
+/// Dump the stats of all the cards into a .csv file at the given path.
+/// Unlike the duelist csv files this one has a header, since it has
+/// too many columns to tell apart otherwise. The columns are the
+/// official card number, name, kind, type, attribute, level, ATK, DEF,
+/// the two guardian stars, the starchip cost and the password. The name
+/// and kind are only there to help the user, and are ignored by
+/// `load_all_cards_csv`.
+pub fn dump_all_cards_csv(
+    csv_path: &std::path::Path,
+    cards: &[Card],
+    card_names: &[String],
+) {
+    let mut csv = Writer::from_path(csv_path).unwrap();
+    csv.write_record([
+        "id",
+        "name",
+        "kind",
+        "type",
+        "attribute",
+        "level",
+        "attack",
+        "defense",
+        "guardian_star_1",
+        "guardian_star_2",
+        "starchips",
+        "password",
+    ])
+    .unwrap();
+
+    for (card_id, card) in cards.iter().enumerate() {
+        csv.write_record([
+            // Shift card_id by 1 to match the official number which
+            // starts at 1.
+            &(card_id + 1).to_string(),
+            &card_names[card_id],
+            card.kind().name(),
+            card.card_type.name(),
+            card.attribute.name(),
+            &card.level.to_string(),
+            &card.attack.to_string(),
+            &card.defense.to_string(),
+            card.guardian_star_1.name(),
+            card.guardian_star_2.name(),
+            &card.starchip_cost.to_string(),
+            &format_password(card.password),
+        ])
+        .unwrap();
+    }
+}
+
+/// Parse a field of a row of cards.csv, panicking with the card and
+/// column if it can't be parsed.
+fn parse_field<T>(
+    card_number: usize,
+    column: &str,
+    field: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> T {
+    return parse(field).unwrap_or_else(|| {
+        panic!("Card {card_number} has invalid {column} \"{field}\"")
+    });
+}
+
+/// Load card stats from a .csv file at the given path, in the format
+/// written by `dump_all_cards_csv`, and update `cards` with them. Like
+/// the duelist csv files this one is sparse: the file might be
+/// missing, and it only needs to have rows for the cards that the mod
+/// changes. Columns can also be left out, or fields left empty, to keep
+/// the stat as it is. Only the id column is required. Passwords must
+/// have all 8 digits, or be `none` to remove the card's password.
+pub fn load_all_cards_csv(csv_path: &std::path::Path, cards: &mut [Card]) {
+    if !csv_path.try_exists().unwrap() {
+        return;
+    }
+
+    let mut csv = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(csv_path)
+        .unwrap();
+    let headers = csv.headers().unwrap().clone();
+    let column_index = |column: &str| {
+        return headers.iter().position(|header| header == column);
+    };
+
+    let id_index = column_index("id").expect("cards.csv has no id column");
+    let type_index = column_index("type");
+    let attribute_index = column_index("attribute");
+    let level_index = column_index("level");
+    let attack_index = column_index("attack");
+    let defense_index = column_index("defense");
+    let guardian_star_1_index = column_index("guardian_star_1");
+    let guardian_star_2_index = column_index("guardian_star_2");
+    let starchips_index = column_index("starchips");
+    let password_index = column_index("password");
+
+    for record_result in csv.records() {
+        let record = record_result.unwrap();
+        // Get a field of the record, unless its column is missing or
+        // it is empty.
+        let field = |index: Option<usize>| {
+            return index
+                .and_then(|index| record.get(index))
+                .filter(|field| !field.is_empty());
+        };
+
+        let card_number =
+            record.get(id_index).unwrap().parse::<usize>().unwrap();
+        assert!(
+            (1..=NUMBER_OF_CARDS).contains(&card_number),
+            "Invalid card number {card_number} in {}",
+            csv_path.display()
+        );
+        // Shift the card number by 1 to get the card_id, which starts
+        // at 0.
+        let card = &mut cards[card_number - 1];
+
+        if let Some(value) = field(type_index) {
+            card.card_type =
+                parse_field(card_number, "type", value, CardType::from_name);
+        }
+        if let Some(value) = field(attribute_index) {
+            card.attribute = parse_field(
+                card_number,
+                "attribute",
+                value,
+                Attribute::from_name,
+            );
+        }
+        if let Some(value) = field(level_index) {
+            card.level = parse_field(card_number, "level", value, |level| {
+                return level.parse().ok();
+            });
+        }
+        if let Some(value) = field(attack_index) {
+            card.attack = parse_field(card_number, "attack", value, |stat| {
+                return stat.parse().ok();
+            });
+        }
+        if let Some(value) = field(defense_index) {
+            card.defense = parse_field(card_number, "defense", value, |stat| {
+                return stat.parse().ok();
+            });
+        }
+        if let Some(value) = field(guardian_star_1_index) {
+            card.guardian_star_1 = parse_field(
+                card_number,
+                "guardian_star_1",
+                value,
+                GuardianStar::from_name,
+            );
+        }
+        if let Some(value) = field(guardian_star_2_index) {
+            card.guardian_star_2 = parse_field(
+                card_number,
+                "guardian_star_2",
+                value,
+                GuardianStar::from_name,
+            );
+        }
+        if let Some(value) = field(starchips_index) {
+            card.starchip_cost =
+                parse_field(card_number, "starchips", value, |cost| {
+                    return cost.parse().ok();
+                });
+        }
+        if let Some(value) = field(password_index) {
+            card.password =
+                parse_field(card_number, "password", value, parse_password);
+        }
+    }
+}
+
+/// Dump the descriptions of all the cards into a .csv file at the given
+/// path, with a row per card: the official card number, the name and
+/// the description. Control codes in the descriptions are written as
+/// markup, see `text::render_markup`, so that every line of a
+/// description fits in a single row. The name is only there to help the
+/// user.
+pub fn dump_descriptions_csv(
+    csv_path: &std::path::Path,
+    cards: &[Card],
+    card_names: &[String],
+) {
+    let mut csv = Writer::from_path(csv_path).unwrap();
+    csv.write_record(["id", "name", "description"]).unwrap();
+
+    for (card_id, card) in cards.iter().enumerate() {
+        // Shift card_id by 1 to match the official number which starts
+        // at 1.
+        csv.write_record([
+            &(card_id + 1).to_string(),
+            &card_names[card_id],
+            &text::render_markup(&card.description),
+        ])
+        .unwrap();
+    }
+}
+
+/// Load descriptions from a .csv file at the given path, in the format
+/// written by `dump_descriptions_csv`, and update `cards` with them.
+/// Like the duelist csv files this one is sparse: the file might be
+/// missing, and it only needs to have rows for the cards whose
+/// description the mod changes. Only the id and description columns
+/// are required.
+pub fn load_descriptions_csv(csv_path: &std::path::Path, cards: &mut [Card]) {
+    if !csv_path.try_exists().unwrap() {
+        return;
+    }
+
+    let mut csv = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(csv_path)
+        .unwrap();
+    let headers = csv.headers().unwrap().clone();
+    let column_index = |column: &str| {
+        return headers.iter().position(|header| header == column);
+    };
+    let id_index =
+        column_index("id").expect("descriptions.csv has no id column");
+    let description_index = column_index("description")
+        .expect("descriptions.csv has no description column");
+
+    for record_result in csv.records() {
+        let record = record_result.unwrap();
+        let card_number =
+            record.get(id_index).unwrap().parse::<usize>().unwrap();
+        assert!(
+            (1..=NUMBER_OF_CARDS).contains(&card_number),
+            "Invalid card number {card_number} in {}",
+            csv_path.display()
+        );
+
+        let description = record.get(description_index).unwrap_or("");
+        // Shift the card number by 1 to get the card_id, which starts
+        // at 0.
+        cards[card_number - 1].description = text::parse_markup(description)
+            .unwrap_or_else(|error| {
+                panic!("Card {card_number} has an invalid description: {error}")
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return a wa_mrg file that is just big enough for the starchip
+    /// table, where every card costs nothing and has the password
+    /// bytes given by `password`.
+    fn wa_mrg_with_passwords(password: impl Fn(usize) -> u32) -> Vec<u8> {
+        let mut wa_mrg =
+            vec![0; STARCHIPS_OFFSET + STARCHIPS_SIZE * NUMBER_OF_CARDS];
+        for card_id in 0..NUMBER_OF_CARDS {
+            let offset = STARCHIPS_OFFSET + STARCHIPS_SIZE * card_id + 4;
+            wa_mrg[offset..offset + 4]
+                .copy_from_slice(&password(card_id).to_le_bytes());
+        }
+        return wa_mrg;
+    }
+
+    fn card_with_password(password: Option<u32>) -> Card {
+        return Card {
+            attack: 0,
+            defense: 0,
+            guardian_star_1: GuardianStar::Mars,
+            guardian_star_2: GuardianStar::Mars,
+            card_type: CardType::Dragon,
+            attribute: Attribute::Light,
+            level: 1,
+            starchip_cost: 0,
+            password,
+            description: Vec::new(),
+        };
+    }
+
+    #[test]
+    fn clearing_a_password_copies_a_card_without_one() {
+        // Not NO_PASSWORD, to tell the copied bytes from the default.
+        let mut wa_mrg = wa_mrg_with_passwords(|card_id| {
+            return if card_id == 3 {
+                0xFFFFFFF0
+            }
+            else {
+                encode_bcd(card_id as u32)
+            };
+        });
+        assert_eq!(read_starchips(&wa_mrg, 3), (0, None));
+
+        write_starchips(&mut wa_mrg, 0, &card_with_password(None));
+        assert_eq!(read_starchips(&wa_mrg, 0), (0, None));
+        let offset = STARCHIPS_OFFSET + 4;
+        assert_eq!(wa_mrg[offset..offset + 4], 0xFFFFFFF0_u32.to_le_bytes());
+    }
+
+    #[test]
+    fn clearing_the_only_password_writes_the_default() {
+        let mut wa_mrg =
+            wa_mrg_with_passwords(|card_id| encode_bcd(card_id as u32));
+
+        write_starchips(&mut wa_mrg, 0, &card_with_password(None));
+        assert_eq!(read_starchips(&wa_mrg, 0), (0, None));
+        let offset = STARCHIPS_OFFSET + 4;
+        assert_eq!(wa_mrg[offset..offset + 4], NO_PASSWORD.to_le_bytes());
+    }
+}
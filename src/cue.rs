@@ -0,0 +1,305 @@
+//! Parsing and writing of cue sheets. A cue sheet is a small text file
+//! that describes the tracks of a disc image and the bin file(s) they
+//! are stored in, eg:
+//!
+//! ```text
+//! FILE "Yu-Gi-Oh! Forbidden Memories.bin" BINARY
+//!   TRACK 01 MODE2/2352
+//!     INDEX 01 00:00:00
+//! ```
+//!
+//! Every track has one or more indices, which are positions within the
+//! bin file given as minutes, seconds and sectors (75 per second).
+//! INDEX 01 is where the track starts. An INDEX 00 before it marks a
+//! pregap that is stored in the file but isn't part of the track. A
+//! PREGAP command instead marks a pregap that isn't stored anywhere.
+//!
+//! We only support the subset of commands that describe the layout of
+//! the disc. Everything else (REM, CATALOG, TITLE etc) is ignored.
+
+use std::fmt;
+
+const SECTORS_PER_SECOND: usize = 75;
+
+pub struct CueTrack {
+    pub number: u8,
+
+    /// Track mode as written in the cue sheet, eg `MODE2/2352` or
+    /// `AUDIO`.
+    pub mode: String,
+
+    /// Length of a pregap that isn't stored in the bin file, in
+    /// sectors.
+    pub pregap: Option<usize>,
+
+    /// Index number and position in the bin file in sectors.
+    pub indices: Vec<(u8, usize)>,
+}
+
+impl CueTrack {
+    /// Position of the start of the track in its bin file, in sectors.
+    /// Panics if the track has no INDEX 01, which every track must
+    /// have.
+    pub fn start_sector(&self) -> usize {
+        return self
+            .indices
+            .iter()
+            .find(|(number, _)| *number == 1)
+            .map(|(_, position)| *position)
+            .unwrap_or_else(|| {
+                panic!("Track {} has no INDEX 01", self.number)
+            });
+    }
+
+    /// Position of the first index of the track in its bin file, in
+    /// sectors. This is where any stored pregap starts, ie where the
+    /// previous track ends.
+    fn first_sector(&self) -> usize {
+        return self
+            .indices
+            .iter()
+            .map(|(_, position)| *position)
+            .min()
+            .unwrap();
+    }
+
+    pub fn is_data(&self) -> bool {
+        return self.mode != "AUDIO";
+    }
+
+    /// Size of each of the track's sectors in its bin file, in bytes.
+    pub fn sector_size(&self) -> usize {
+        return match self.mode.as_str() {
+            "AUDIO" | "MODE1/2352" | "MODE2/2352" => 2352,
+            "MODE2/2336" => 2336,
+            "MODE1/2048" | "MODE2/2048" => 2048,
+            _ => panic!("Unsupported track mode {}", self.mode),
+        };
+    }
+}
+
+/// Convert a position in sectors in a bin file with the given tracks
+/// to a position in bytes. Each track's sectors have the size of its
+/// own mode, so audio tracks before the data track can have a different
+/// size than the data track's sectors.
+fn position_in_bytes(tracks: &[CueTrack], position: usize) -> u64 {
+    let mut bytes = 0;
+    for (i, track) in tracks.iter().enumerate() {
+        let track_start = if i == 0 { 0 } else { track.first_sector() };
+        if position <= track_start {
+            break;
+        }
+
+        let track_end = tracks
+            .get(i + 1)
+            .map_or(usize::MAX, |next_track| next_track.first_sector());
+        bytes += (position.min(track_end) - track_start) * track.sector_size();
+    }
+    return bytes as u64;
+}
+
+pub struct CueFile {
+    /// Path of the bin file as written in the cue sheet. Relative paths
+    /// are relative to the directory of the cue sheet.
+    pub path: String,
+
+    pub tracks: Vec<CueTrack>,
+}
+
+pub struct CueSheet {
+    pub files: Vec<CueFile>,
+}
+
+/// Location of the data track, as resolved from a cue sheet.
+pub struct DataTrack {
+    pub bin_path: std::path::PathBuf,
+
+    pub mode: String,
+
+    /// Range of bytes of the track within the bin file. The end is
+    /// `None` if the track extends to the end of the file.
+    pub start_offset: u64,
+    pub end_offset: Option<u64>,
+
+    /// Whether the cue sheet describes any tracks other than the data
+    /// track.
+    pub has_other_tracks: bool,
+}
+
+/// Parse a position in the form `mm:ss:ff` and return it in sectors.
+fn parse_msf(msf: &str) -> usize {
+    let parts = msf
+        .split(':')
+        .map(|part| part.parse::<usize>().expect("Invalid cue sheet time"))
+        .collect::<Vec<usize>>();
+    assert!(parts.len() == 3, "Invalid cue sheet time {msf}");
+
+    return (parts[0] * 60 + parts[1]) * SECTORS_PER_SECOND + parts[2];
+}
+
+fn format_msf(sectors: usize) -> String {
+    let minutes = sectors / (60 * SECTORS_PER_SECOND);
+    let seconds = sectors / SECTORS_PER_SECOND % 60;
+    let frames = sectors % SECTORS_PER_SECOND;
+    return format!("{minutes:02}:{seconds:02}:{frames:02}");
+}
+
+/// Parse the file name of a FILE command. It is usually quoted because
+/// it may contain spaces, and it is followed by the file type.
+fn parse_file_name(arguments: &str) -> String {
+    let arguments = arguments.trim();
+
+    if let Some(quoted) = arguments.strip_prefix('"') {
+        let end = quoted.find('"').expect("Unterminated quote in cue sheet");
+        return quoted[..end].to_string();
+    }
+
+    return match arguments.rsplit_once(char::is_whitespace) {
+        Some((name, _file_type)) => name.to_string(),
+        None => arguments.to_string(),
+    };
+}
+
+/// Parse the text of a cue sheet. Panics if the cue sheet is malformed.
+pub fn parse_cue_sheet(text: &str) -> CueSheet {
+    let mut cue_sheet = CueSheet { files: Vec::new() };
+
+    for line in text.lines() {
+        let line = line.trim();
+        let (command, arguments) =
+            line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mut words = arguments.split_whitespace();
+
+        match command.to_uppercase().as_str() {
+            "FILE" => {
+                cue_sheet.files.push(CueFile {
+                    path: parse_file_name(arguments),
+                    tracks: Vec::new(),
+                });
+            }
+            "TRACK" => {
+                let file = cue_sheet
+                    .files
+                    .last_mut()
+                    .expect("TRACK before FILE in cue sheet");
+                file.tracks.push(CueTrack {
+                    number: words.next().unwrap().parse().unwrap(),
+                    mode: words.next().unwrap().to_uppercase(),
+                    pregap: None,
+                    indices: Vec::new(),
+                });
+            }
+            "INDEX" | "PREGAP" => {
+                let track = cue_sheet
+                    .files
+                    .last_mut()
+                    .and_then(|file| file.tracks.last_mut())
+                    .unwrap_or_else(|| panic!("{command} before TRACK"));
+
+                if command.eq_ignore_ascii_case("INDEX") {
+                    let number = words.next().unwrap().parse().unwrap();
+                    let position = parse_msf(words.next().unwrap());
+                    track.indices.push((number, position));
+                }
+                else {
+                    track.pregap = Some(parse_msf(words.next().unwrap()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    return cue_sheet;
+}
+
+impl CueSheet {
+    /// A cue sheet for a single bin file that contains only the data
+    /// track of the game, stored with the given track mode.
+    pub fn single_track(bin_name: &str, mode: &str) -> CueSheet {
+        return CueSheet {
+            files: vec![CueFile {
+                path: bin_name.to_string(),
+                tracks: vec![CueTrack {
+                    number: 1,
+                    mode: mode.to_string(),
+                    pregap: None,
+                    indices: vec![(1, 0)],
+                }],
+            }],
+        };
+    }
+
+    /// Find the first data track. The game only has one, but some rips
+    /// might add audio tracks or split the image into several files.
+    /// The track ends where the next track in the same file starts,
+    /// including that track's stored pregap.
+    pub fn find_data_track(&self, cue_dir: &std::path::Path) -> DataTrack {
+        let track_count = self
+            .files
+            .iter()
+            .map(|file| file.tracks.len())
+            .sum::<usize>();
+
+        for file in &self.files {
+            for (i, track) in file.tracks.iter().enumerate() {
+                if !track.is_data() {
+                    continue;
+                }
+
+                return DataTrack {
+                    bin_path: cue_dir.join(&file.path),
+                    mode: track.mode.clone(),
+                    start_offset: position_in_bytes(
+                        &file.tracks,
+                        track.start_sector(),
+                    ),
+                    end_offset: file.tracks.get(i + 1).map(|next_track| {
+                        return position_in_bytes(
+                            &file.tracks,
+                            next_track.first_sector(),
+                        );
+                    }),
+                    has_other_tracks: track_count > 1,
+                };
+            }
+        }
+
+        panic!("No data track in cue sheet");
+    }
+}
+
+impl fmt::Display for CueSheet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for file in &self.files {
+            writeln!(f, "FILE \"{}\" BINARY", file.path)?;
+
+            for track in &file.tracks {
+                writeln!(f, "  TRACK {:02} {}", track.number, track.mode)?;
+                if let Some(pregap) = track.pregap {
+                    writeln!(f, "    PREGAP {}", format_msf(pregap))?;
+                }
+                for (number, position) in &track.indices {
+                    writeln!(
+                        f,
+                        "    INDEX {:02} {}",
+                        number,
+                        format_msf(*position)
+                    )?;
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// Load a cue sheet from the given path.
+pub fn load_cue_sheet(cue_path: &std::path::Path) -> CueSheet {
+    let text = std::fs::read_to_string(cue_path).expect("Failed to load file");
+    return parse_cue_sheet(&text);
+}
+
+/// Write a cue sheet to the given path.
+pub fn dump_cue_sheet(cue_path: &std::path::Path, cue_sheet: &CueSheet) {
+    std::fs::write(cue_path, cue_sheet.to_string()).unwrap();
+}
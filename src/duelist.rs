@@ -5,6 +5,7 @@
 
 use csv::{ReaderBuilder, Writer};
 
+use crate::image::{SlusExecutable, WaMrgArchive};
 use crate::text;
 
 // I don't know if there is any way in the ROM to figure out where the
@@ -16,12 +17,12 @@ pub const NUMBER_OF_DUELISTS: usize = 39;
 // Card rates are stored as 2 bytes
 pub const CARDLIST_SIZE: usize = 2 * NUMBER_OF_CARDS;
 
-const CARD_NAME_INDICES_OFFSET: usize = 0x1C6002;
-const NAME_OFFSET: usize = 0x1C0800;
+pub const CARD_NAME_INDICES_OFFSET: usize = 0x1C6002;
+pub const NAME_OFFSET: usize = 0x1C0800;
 
-const DUELIST_DATA_OFFSET: usize = 0xE9B000;
-const DUELIST_NAME_INDICES_OFFSET: usize = 0x1C6652;
-const DUELIST_DATA_SIZE: usize = 0x1800;
+pub const DUELIST_DATA_OFFSET: usize = 0xE9B000;
+pub const DUELIST_NAME_INDICES_OFFSET: usize = 0x1C6652;
+pub const DUELIST_DATA_SIZE: usize = 0x1800;
 const DUELIST_DECK_RELATIVE_OFFSET: usize = 0x0;
 const DUELIST_SAPOW_OFFSET: usize = 0x5B4;
 const DUELIST_BCD_OFFSET: usize = 0xB68;
@@ -30,10 +31,17 @@ const DUELIST_SATEC_OFFSET: usize = 0x111C;
 /// A list of weights corresponding to each card. These are used to:
 /// - Generate the duelist's deck
 /// - Determine the card dropped at the end of a victory
+#[derive(PartialEq, Eq)]
 pub struct CardList {
     pub card_rate: [u16; NUMBER_OF_CARDS],
 }
 
+impl Default for CardList {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
 impl CardList {
     /// Create a CardList where all weights are `0`. This is not a valid
     /// card list according to the game logic and it should be modified
@@ -47,7 +55,13 @@ impl CardList {
     /// Check that a CardList is valid. This means that all weights
     /// should add to 2048.
     pub fn is_valid(&self) -> bool {
-        return self.card_rate.iter().sum::<u16>() == 2048;
+        return self.total_rate() == 2048;
+    }
+
+    /// Return the sum of all weights. It is summed as a u32 so that an
+    /// invalid list can't overflow it.
+    pub fn total_rate(&self) -> u32 {
+        return self.card_rate.iter().map(|&rate| rate as u32).sum();
     }
 
     pub fn print(&self) {
@@ -67,6 +81,12 @@ pub struct Duelist {
     pub drops_bcd: CardList,
 }
 
+impl Default for Duelist {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
 impl Duelist {
     pub fn new() -> Duelist {
         return Duelist {
@@ -77,10 +97,22 @@ impl Duelist {
             drops_sa_tec: CardList::new(),
         };
     }
+
+    /// The card lists of the duelist, along with the name of the .csv
+    /// file that each one is dumped to.
+    pub fn card_lists(&self) -> [(&'static str, &CardList); 4] {
+        return [
+            ("deck.csv", &self.deck),
+            ("drops-bcd.csv", &self.drops_bcd),
+            ("drops-sa-pow.csv", &self.drops_sa_pow),
+            ("drops-sa-tec.csv", &self.drops_sa_tec),
+        ];
+    }
 }
 
 /// Read all the card names from the given slus file.
-pub fn get_card_names(slus: &Vec<u8>) -> Vec<String> {
+pub fn get_card_names(slus: &SlusExecutable) -> Vec<String> {
+    let slus = slus.as_bytes();
     let mut card_names = Vec::new();
 
     for i in 0..NUMBER_OF_CARDS {
@@ -141,10 +173,12 @@ fn write_card_list_to_slice(card_list: &CardList, target: &mut [u8]) {
 
 /// Read a single duelists info.
 fn read_duelist(
-    slus: &Vec<u8>,
-    wa_mrg: &Vec<u8>,
+    slus: &SlusExecutable,
+    wa_mrg: &WaMrgArchive,
     duelist_id: usize,
 ) -> Duelist {
+    let slus = slus.as_bytes();
+    let wa_mrg = wa_mrg.as_bytes();
     let mut duelist_info = Duelist::new();
 
     // The game stores a relative offset starting from NAME_OFFSET
@@ -185,10 +219,12 @@ fn read_duelist(
 
 /// Write a single duelist into the given wa_mrg file.
 fn write_duelist(
-    wa_mrg: &mut Vec<u8>,
+    wa_mrg: &mut WaMrgArchive,
     duelist_id: usize,
     duelist_info: &Duelist,
 ) {
+    let wa_mrg = wa_mrg.as_bytes_mut();
+
     // Relative offset from the start of the duelist data array.
     let current_duelist_offset =
         DUELIST_DATA_OFFSET + (DUELIST_DATA_SIZE * duelist_id);
@@ -218,7 +254,10 @@ fn write_duelist(
 
 /// Read all the duelists from the given slus and wa_mrg files. Return
 /// them as a vector.
-pub fn read_all_duelists(slus: &Vec<u8>, wa_mrg: &Vec<u8>) -> Vec<Duelist> {
+pub fn read_all_duelists(
+    slus: &SlusExecutable,
+    wa_mrg: &WaMrgArchive,
+) -> Vec<Duelist> {
     let mut duelists = Vec::new();
 
     for duelist_id in 0..NUMBER_OF_DUELISTS {
@@ -230,14 +269,13 @@ pub fn read_all_duelists(slus: &Vec<u8>, wa_mrg: &Vec<u8>) -> Vec<Duelist> {
     return duelists;
 }
 
-/// Write all duelist data into the given wa_mrg. Modifying the duelist
-/// name is not supported at this moment so we don't need to touch the
-/// slus file.
-pub fn write_all_duelists(wa_mrg: &mut Vec<u8>, duelists: &[Duelist]) {
+/// Write all duelist data into the given wa_mrg. Duelist names are in
+/// the slus file and are written separately, see the `name` module.
+pub fn write_all_duelists(wa_mrg: &mut WaMrgArchive, duelists: &[Duelist]) {
     assert!(duelists.len() == NUMBER_OF_DUELISTS);
 
-    for duelist_id in 0..NUMBER_OF_DUELISTS {
-        write_duelist(wa_mrg, duelist_id, &duelists[duelist_id]);
+    for (duelist_id, duelist_info) in duelists.iter().enumerate() {
+        write_duelist(wa_mrg, duelist_id, duelist_info);
     }
 }
 
@@ -259,7 +297,7 @@ fn dump_cardlist_csv(
     let mut csv = Writer::from_path(csv_path).unwrap();
     for (card_id, card_rate) in cardlist.card_rate.iter().enumerate() {
         if *card_rate != 0 {
-            csv.write_record(&[
+            csv.write_record([
                 // Shift card_id by 1 to match the official number which
                 // starts at 1.
                 &(card_id + 1).to_string(),
@@ -272,6 +310,9 @@ fn dump_cardlist_csv(
 }
 
 /// Load a cardlist from a .csv file at the given path.
+///
+/// Panics with the path and line of the first invalid row, or with the
+/// path if the weights don't add up to 2048.
 fn load_cardlist_csv(csv_path: &std::path::Path) -> CardList {
     let mut card_list = CardList::new();
     let mut csv = ReaderBuilder::new()
@@ -281,25 +322,45 @@ fn load_cardlist_csv(csv_path: &std::path::Path) -> CardList {
 
     for record_result in csv.records() {
         let record = record_result.unwrap();
-        // Shift card_id by 1 to match the official number which
-        // starts at 1.
-        let card_id = record.get(0).unwrap().parse::<usize>().unwrap() - 1;
-        let card_rate = record.get(1).unwrap().parse::<u16>().unwrap();
-
+        let line = record.position().unwrap().line();
         // We don't have to check that the numbers are >0 because they
         // are unsigned types. If they are negative, they will simply
-        // fail to parse above.
-        assert!(card_id < NUMBER_OF_CARDS);
-        assert!(card_rate < 2048);
-
-        card_list.card_rate[card_id] = card_rate;
+        // fail to parse.
+        let parse_field = |index: usize, column: &str| {
+            let field = record.get(index).unwrap_or("");
+            return field.parse::<u16>().unwrap_or_else(|_| {
+                panic!(
+                    "{}:{line}: invalid {column} \"{field}\"",
+                    csv_path.display()
+                )
+            });
+        };
+        let card_number = parse_field(0, "card number") as usize;
+        let card_rate = parse_field(1, "weight");
+
+        assert!(
+            (1..=NUMBER_OF_CARDS).contains(&card_number),
+            "{}:{line}: invalid card number {card_number}, it must be from 1 \
+             to {NUMBER_OF_CARDS}",
+            csv_path.display()
+        );
+        assert!(
+            card_rate < 2048,
+            "{}:{line}: invalid weight {card_rate}, it must be less than 2048",
+            csv_path.display()
+        );
+
+        // Shift the card number by 1 to get the card_id, which starts
+        // at 0.
+        card_list.card_rate[card_number - 1] = card_rate;
     }
 
-    if !card_list.is_valid() {
-        println!("Invalid card list at {}", csv_path.display());
-        card_list.print();
-        panic!();
-    }
+    assert!(
+        card_list.is_valid(),
+        "{}: the weights add up to {}, but they must add up to 2048",
+        csv_path.display(),
+        card_list.total_rate()
+    );
     return card_list;
 }
 
@@ -314,22 +375,9 @@ fn dump_duelist_csv(
     duelist: &Duelist,
     card_names: &[String],
 ) {
-    dump_cardlist_csv(&dir_path.join("deck.csv"), &duelist.deck, &card_names);
-    dump_cardlist_csv(
-        &dir_path.join("drops-bcd.csv"),
-        &duelist.drops_bcd,
-        &card_names,
-    );
-    dump_cardlist_csv(
-        &dir_path.join("drops-sa-pow.csv"),
-        &duelist.drops_sa_pow,
-        &card_names,
-    );
-    dump_cardlist_csv(
-        &dir_path.join("drops-sa-tec.csv"),
-        &duelist.drops_sa_tec,
-        &card_names,
-    );
+    for (file_name, card_list) in duelist.card_lists() {
+        dump_cardlist_csv(&dir_path.join(file_name), card_list, card_names);
+    }
 }
 
 /// Load all duelist data from a collection of .csv's under the given
@@ -379,22 +427,166 @@ pub fn dump_all_duelists_csv(
             .join((duelist_id + 1).to_string() + "." + &duelist.name);
         std::fs::create_dir(&duelist_dir).unwrap();
 
-        dump_duelist_csv(&duelist_dir, &duelist, &card_names);
+        dump_duelist_csv(&duelist_dir, duelist, card_names);
+    }
+}
+
+/// Dump only the cardlists of `duelists` that differ from
+/// `base_duelists`, in the same layout as `dump_all_duelists_csv`.
+/// Duelists without any changes get no directory at all. The result is
+/// a sparse mod that `load_all_duelists_csv` turns the base duelists
+/// into the given ones with, so the directories are named after the
+/// base duelists. Return the paths of the files written.
+pub fn dump_changed_duelists_csv(
+    top_level_dir: &std::path::Path,
+    base_duelists: &[Duelist],
+    duelists: &[Duelist],
+    card_names: &[String],
+) -> Vec<std::path::PathBuf> {
+    assert!(base_duelists.len() == duelists.len());
+
+    std::fs::create_dir_all(top_level_dir).unwrap();
+    let mut written_paths = Vec::new();
+    for (duelist_id, (base_duelist, duelist)) in
+        base_duelists.iter().zip(duelists).enumerate()
+    {
+        let duelist_dir = top_level_dir
+            .join((duelist_id + 1).to_string() + "." + &base_duelist.name);
+
+        for ((file_name, base_card_list), (_, card_list)) in base_duelist
+            .card_lists()
+            .into_iter()
+            .zip(duelist.card_lists())
+        {
+            if base_card_list == card_list {
+                continue;
+            }
+
+            std::fs::create_dir_all(&duelist_dir).unwrap();
+            let csv_path = duelist_dir.join(file_name);
+            dump_cardlist_csv(&csv_path, card_list, card_names);
+            written_paths.push(csv_path);
+        }
+    }
+
+    return written_paths;
+}
+
+/// Find the directory of each duelist under the given directory, by
+/// the number before the first "." of its name. The rest of the name
+/// is only there to help the user, so "2.Duelist 2" and "2.Kaiba" are
+/// both the directory of the second duelist, eg after renaming it in
+/// names.csv. Entries that don't start with a number and a "." are
+/// ignored. Return the paths by duelist id.
+///
+/// Panics if two directories have the same number, or if a number
+/// doesn't belong to any duelist.
+fn find_duelist_dirs(
+    top_level_dir: &std::path::Path,
+) -> Vec<Option<std::path::PathBuf>> {
+    let mut duelist_dirs = vec![None; NUMBER_OF_DUELISTS];
+    if !top_level_dir.try_exists().unwrap() {
+        return duelist_dirs;
+    }
+
+    for entry_result in std::fs::read_dir(top_level_dir).unwrap() {
+        let path = entry_result.unwrap().path();
+        if !path.is_dir() {
+            continue;
+        }
+        let Some(duelist_number) = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.split_once('.'))
+            .and_then(|(number, _)| number.parse::<usize>().ok())
+        else {
+            continue;
+        };
+
+        assert!(
+            (1..=NUMBER_OF_DUELISTS).contains(&duelist_number),
+            "Invalid duelist number {duelist_number} in {}, it must be from 1 \
+             to {NUMBER_OF_DUELISTS}",
+            path.display()
+        );
+        // Shift the number by 1 to get the duelist id, which starts at
+        // 0.
+        let duelist_dir = &mut duelist_dirs[duelist_number - 1];
+        if let Some(other_path) = duelist_dir {
+            panic!(
+                "Both {} and {} are directories of duelist {duelist_number}",
+                other_path.display(),
+                path.display()
+            );
+        }
+        *duelist_dir = Some(path);
     }
+
+    return duelist_dirs;
 }
 
-/// Load all the duelists from csv files and return them as a vector.
+/// Load all the duelists from csv files under the given directory, see
+/// `find_duelist_dirs`, and update `duelists` with them.
 pub fn load_all_duelists_csv(
     top_level_dir: &std::path::Path,
-    duelists: &mut Vec<Duelist>,
+    duelists: &mut [Duelist],
 ) {
-    for (duelist_id, duelist) in duelists.iter_mut().enumerate() {
-        // TODO: Allow directory names in the form "1. <ARBITRARY
-        // NAME>". The name would exist to aid the user and would be
-        // completely ignored by us.
-        let duelist_dir = top_level_dir
-            .join((duelist_id + 1).to_string() + "." + &duelist.name);
+    assert!(duelists.len() == NUMBER_OF_DUELISTS);
 
-        load_duelist_csv(&duelist_dir, duelist);
+    let duelist_dirs = find_duelist_dirs(top_level_dir);
+    for (duelist, duelist_dir) in duelists.iter_mut().zip(duelist_dirs) {
+        if let Some(duelist_dir) = duelist_dir {
+            load_duelist_csv(&duelist_dir, duelist);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a mod whose deck of the second duelist has cards 7 and 8,
+    /// in a directory named `duelist_dir_name`, and load it into
+    /// duelists whose second one is named `duelist_name`.
+    fn load_renamed_deck(
+        duelist_dir_name: &str,
+        duelist_name: &str,
+    ) -> Vec<Duelist> {
+        let top_level_dir = std::env::temp_dir().join(format!(
+            "fmde-test-{}-{duelist_dir_name}-{duelist_name}",
+            std::process::id()
+        ));
+        let duelist_dir = top_level_dir.join(duelist_dir_name);
+        std::fs::create_dir_all(&duelist_dir).unwrap();
+        std::fs::write(duelist_dir.join("deck.csv"), "7,1024\n8,1024\n")
+            .unwrap();
+        std::fs::write(top_level_dir.join("names.csv"), "").unwrap();
+
+        let mut duelists: Vec<_> = (0..NUMBER_OF_DUELISTS)
+            .map(|duelist_id| {
+                let mut duelist = Duelist::new();
+                duelist.name = format!("Duelist {}", duelist_id + 1);
+                return duelist;
+            })
+            .collect();
+        duelists[1].name = duelist_name.to_string();
+        load_all_duelists_csv(&top_level_dir, &mut duelists);
+
+        std::fs::remove_dir_all(&top_level_dir).unwrap();
+        return duelists;
+    }
+
+    #[test]
+    fn duelist_directories_are_found_by_number() {
+        // The directory that `dump` wrote, with a mod that renames the
+        // duelist in names.csv, and the other way around.
+        for (duelist_dir_name, duelist_name) in
+            [("2.Duelist 2", "Kaiba"), ("2.Kaiba", "Duelist 2")]
+        {
+            let duelists = load_renamed_deck(duelist_dir_name, duelist_name);
+            assert_eq!(duelists[1].deck.card_rate[6], 1024);
+            assert_eq!(duelists[1].deck.card_rate[7], 1024);
+            assert!(duelists[0].deck.card_rate.iter().all(|&rate| rate == 0));
+        }
     }
 }
@@ -0,0 +1,226 @@
+//! Module to manipulate the table of which monsters each equip card can
+//! be used on. The function names follow the same convention as the
+//! duelist module:
+//! - read/write if they operate on the ROM file
+//! - load/dump if they operate on csv files
+//!
+//! The table is in the wa_mrg file, at `EQUIP_TABLE_OFFSET`, right
+//! before the fusion table. It is a list of entries, each with:
+//! - the card number of the equip card, in 2 bytes
+//! - the number of monsters it can be used on, in 2 bytes
+//! - the card number of each monster, in 2 bytes
+//!
+//! The list ends with an equip card number of zero. All numbers are
+//! little-endian.
+
+use csv::{ReaderBuilder, Writer};
+
+use crate::duelist::NUMBER_OF_CARDS;
+use crate::image::WaMrgArchive;
+
+pub const EQUIP_TABLE_OFFSET: usize = 0xB85000;
+
+/// The space reserved for the equip table, up to the start of the
+/// fusion table.
+pub const EQUIP_TABLE_SIZE: usize = 0x2800;
+
+/// The error for an equip table that takes `size` bytes, more than
+/// `EQUIP_TABLE_SIZE`.
+#[derive(Debug)]
+pub struct EquipTableTooBigError {
+    pub size: usize,
+}
+
+impl std::fmt::Display for EquipTableTooBigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(
+            f,
+            "The equip table takes {} bytes, but only {EQUIP_TABLE_SIZE} are \
+             reserved for it",
+            self.size
+        );
+    }
+}
+
+/// The monsters that each card can be equipped to, by card id. Cards
+/// that aren't equip cards have no monsters.
+#[derive(PartialEq, Eq)]
+pub struct EquipTable {
+    pub monsters: Vec<Vec<usize>>,
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> usize {
+    let low_byte: usize = data[offset].into();
+    let high_byte: usize = data[offset + 1].into();
+    return (high_byte << 8) + low_byte;
+}
+
+fn write_u16_le(data: &mut Vec<u8>, value: usize) {
+    data.push(value as u8);
+    data.push((value >> 8) as u8);
+}
+
+/// Convert a card number read from the equip table to a card id.
+fn read_card_id(card_number: usize) -> usize {
+    assert!(
+        (1..=NUMBER_OF_CARDS).contains(&card_number),
+        "Invalid card number {card_number} in the equip table"
+    );
+    return card_number - 1;
+}
+
+/// Read the equip table from the given wa_mrg file.
+pub fn read_equip_table(wa_mrg: &WaMrgArchive) -> EquipTable {
+    let table = &wa_mrg.as_bytes()
+        [EQUIP_TABLE_OFFSET..EQUIP_TABLE_OFFSET + EQUIP_TABLE_SIZE];
+    let mut monsters = vec![Vec::new(); NUMBER_OF_CARDS];
+
+    let mut offset = 0;
+    loop {
+        let equip_number = read_u16_le(table, offset);
+        if equip_number == 0 {
+            break;
+        }
+
+        let equip_id = read_card_id(equip_number);
+        let count = read_u16_le(table, offset + 2);
+        offset += 4;
+        for _ in 0..count {
+            monsters[equip_id].push(read_card_id(read_u16_le(table, offset)));
+            offset += 2;
+        }
+    }
+
+    return EquipTable { monsters };
+}
+
+/// Write the equip table into the given wa_mrg file, with the equip
+/// cards in order. Only the bytes that the new table takes up, up to
+/// its terminator, are written: we don't know whether the game uses the
+/// rest of `EQUIP_TABLE_SIZE`, so it keeps its original bytes.
+///
+/// The table is left untouched if it is the same as the one in the
+/// file, so that the original layout is kept when there is nothing to
+/// change.
+///
+/// Fails if the encoded table doesn't fit in `EQUIP_TABLE_SIZE`, before
+/// writing anything.
+pub fn write_equip_table(
+    wa_mrg: &mut WaMrgArchive,
+    equip_table: &EquipTable,
+) -> Result<(), EquipTableTooBigError> {
+    assert!(equip_table.monsters.len() == NUMBER_OF_CARDS);
+    if read_equip_table(wa_mrg) == *equip_table {
+        return Ok(());
+    }
+
+    let mut table_data = Vec::new();
+    for (equip_id, monsters) in equip_table.monsters.iter().enumerate() {
+        if monsters.is_empty() {
+            continue;
+        }
+
+        // Shift the ids by 1 to get the card numbers, which start at 1.
+        write_u16_le(&mut table_data, equip_id + 1);
+        write_u16_le(&mut table_data, monsters.len());
+        for &monster_id in monsters {
+            assert!(monster_id < NUMBER_OF_CARDS);
+            write_u16_le(&mut table_data, monster_id + 1);
+        }
+    }
+    write_u16_le(&mut table_data, 0);
+
+    if table_data.len() > EQUIP_TABLE_SIZE {
+        return Err(EquipTableTooBigError {
+            size: table_data.len(),
+        });
+    }
+
+    let table = &mut wa_mrg.as_bytes_mut()
+        [EQUIP_TABLE_OFFSET..EQUIP_TABLE_OFFSET + EQUIP_TABLE_SIZE];
+    table[..table_data.len()].copy_from_slice(&table_data);
+
+    return Ok(());
+}
+
+/// Dump the equip table into a .csv file at the given path, with a row
+/// for each monster that each equip card can be used on:
+///
+/// equip_id,monster_id,equip_name,monster_name
+///
+/// The names are only there to help the user.
+pub fn dump_equips_csv(
+    csv_path: &std::path::Path,
+    equip_table: &EquipTable,
+    card_names: &[String],
+) {
+    let mut csv = Writer::from_path(csv_path).unwrap();
+    csv.write_record(["equip_id", "monster_id", "equip_name", "monster_name"])
+        .unwrap();
+
+    for (equip_id, monsters) in equip_table.monsters.iter().enumerate() {
+        for &monster_id in monsters {
+            // Shift the ids by 1 to match the official numbers which
+            // start at 1.
+            csv.write_record([
+                &(equip_id + 1).to_string(),
+                &(monster_id + 1).to_string(),
+                &card_names[equip_id],
+                &card_names[monster_id],
+            ])
+            .unwrap();
+        }
+    }
+}
+
+/// Parse an official card number from a field of equips.csv and
+/// convert it to a card id.
+fn parse_card_number(csv_path: &std::path::Path, field: &str) -> usize {
+    let card_number = field.parse::<usize>().unwrap_or(0);
+    assert!(
+        (1..=NUMBER_OF_CARDS).contains(&card_number),
+        "Invalid card number \"{field}\" in {}",
+        csv_path.display()
+    );
+    return card_number - 1;
+}
+
+/// Load the equip table from a .csv file at the given path, in the
+/// format written by `dump_equips_csv`, and update `equip_table` with
+/// it. Like the fusions, the file might be missing, and the monsters of
+/// each equip card that appears in it are replaced by its rows. To make
+/// an equip card usable on no monster at all, give it a single row with
+/// an empty monster.
+pub fn load_equips_csv(
+    csv_path: &std::path::Path,
+    equip_table: &mut EquipTable,
+) {
+    if !csv_path.try_exists().unwrap() {
+        return;
+    }
+
+    let mut csv = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(csv_path)
+        .unwrap();
+
+    let mut loaded_equips = vec![false; NUMBER_OF_CARDS];
+    for record_result in csv.records() {
+        let record = record_result.unwrap();
+        let equip_id = parse_card_number(csv_path, record.get(0).unwrap());
+        let monster = record.get(1).unwrap_or("");
+
+        // Forget the equip card's monsters from the ROM the first time
+        // we see it, and add every monster that follows.
+        let monsters = &mut equip_table.monsters[equip_id];
+        if !loaded_equips[equip_id] {
+            monsters.clear();
+            loaded_equips[equip_id] = true;
+        }
+
+        if monster.is_empty() {
+            continue;
+        }
+        monsters.push(parse_card_number(csv_path, monster));
+    }
+}
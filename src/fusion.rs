@@ -0,0 +1,356 @@
+//! Module to manipulate the fusion table. The function names follow the
+//! same convention as the duelist module:
+//! - read/write if they operate on the ROM file
+//! - load/dump if they operate on csv files
+//!
+//! The table is in the wa_mrg file, at `FUSION_TABLE_OFFSET`. It starts
+//! with 2 bytes we don't know the meaning of, followed by a 2 byte
+//! pointer for each card, relative to the start of the table. Cards
+//! without fusions have a null pointer. Each pointer leads to the
+//! card's list of fusions:
+//! - the number of fusions, in a single byte, or if there are more than
+//!   255 a zero byte followed by 511 minus the number of fusions
+//! - the fusions, in groups of 5 bytes that hold two (partner, result)
+//!   pairs of 10 bit card numbers. The first byte holds the top 2 bits
+//!   of each card number, lowest bits first, and the other 4 bytes hold
+//!   the low 8 bits of the first partner, first result, second partner
+//!   and second result. If the number of fusions is odd the second pair
+//!   of the last group is unused.
+
+use csv::{ReaderBuilder, Writer};
+
+use crate::duelist::NUMBER_OF_CARDS;
+use crate::image::WaMrgArchive;
+
+pub const FUSION_TABLE_OFFSET: usize = 0xB87800;
+
+/// The space reserved for the fusion table. Its pointers are 2 bytes,
+/// so it can't grow any larger anyway.
+pub const FUSION_TABLE_SIZE: usize = 0x10000;
+
+const FUSION_POINTERS_RELATIVE_OFFSET: usize = 2;
+const FUSION_LISTS_RELATIVE_OFFSET: usize =
+    FUSION_POINTERS_RELATIVE_OFFSET + 2 * NUMBER_OF_CARDS;
+
+/// Lists with more fusions than this store their length in 2 bytes.
+const MAX_SHORT_COUNT: usize = 0xFF;
+const MAX_COUNT: usize = 511;
+
+const FUSION_GROUP_SIZE: usize = 5;
+
+/// Why a fusion table doesn't fit in the ROM.
+#[derive(Debug)]
+pub enum FusionTableError {
+    /// A card has more fusions than its count can hold, see
+    /// `MAX_COUNT`.
+    TooManyFusions { card_number: usize, count: usize },
+
+    /// The table takes `size` bytes, more than `FUSION_TABLE_SIZE`.
+    TooBig { size: usize },
+}
+
+impl std::fmt::Display for FusionTableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return match self {
+            FusionTableError::TooManyFusions { card_number, count } => write!(
+                f,
+                "Card {card_number} has {count} fusions, but at most \
+                 {MAX_COUNT} fit in the fusion table"
+            ),
+            FusionTableError::TooBig { size } => write!(
+                f,
+                "The fusion table takes {size} bytes, but only \
+                 {FUSION_TABLE_SIZE} are reserved for it"
+            ),
+        };
+    }
+}
+
+/// A fusion of a card with `partner`, which gives `result`. Both are
+/// card ids, which start at 0.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Fusion {
+    pub partner: usize,
+    pub result: usize,
+}
+
+/// The fusions of every card, indexed by card id. Each fusion is only
+/// listed under one of its two cards.
+#[derive(PartialEq, Eq)]
+pub struct FusionTable {
+    pub fusions: Vec<Vec<Fusion>>,
+}
+
+/// Read the 10 bit card number at `low_byte`, whose top 2 bits are
+/// `high_bits`, and convert it to a card id.
+fn read_card_id(high_bits: u8, low_byte: u8) -> usize {
+    let card_number = ((high_bits as usize & 3) << 8) | low_byte as usize;
+    assert!(
+        (1..=NUMBER_OF_CARDS).contains(&card_number),
+        "Invalid card number {card_number} in the fusion table"
+    );
+    return card_number - 1;
+}
+
+/// Read a single card's list of fusions, starting at its count.
+fn read_fusion_list(list_data: &[u8]) -> Vec<Fusion> {
+    let (count, mut offset) = if list_data[0] == 0 {
+        (MAX_COUNT - list_data[1] as usize, 2)
+    }
+    else {
+        (list_data[0] as usize, 1)
+    };
+
+    let mut fusions = Vec::new();
+    while fusions.len() < count {
+        let group = &list_data[offset..offset + FUSION_GROUP_SIZE];
+        let high_bits = group[0];
+
+        fusions.push(Fusion {
+            partner: read_card_id(high_bits, group[1]),
+            result: read_card_id(high_bits >> 2, group[2]),
+        });
+        if fusions.len() < count {
+            fusions.push(Fusion {
+                partner: read_card_id(high_bits >> 4, group[3]),
+                result: read_card_id(high_bits >> 6, group[4]),
+            });
+        }
+
+        offset += FUSION_GROUP_SIZE;
+    }
+
+    return fusions;
+}
+
+/// Read the fusion table from the given wa_mrg file.
+pub fn read_fusion_table(wa_mrg: &WaMrgArchive) -> FusionTable {
+    let table = &wa_mrg.as_bytes()
+        [FUSION_TABLE_OFFSET..FUSION_TABLE_OFFSET + FUSION_TABLE_SIZE];
+    let mut fusions = Vec::new();
+
+    for card_id in 0..NUMBER_OF_CARDS {
+        let pointer_offset = FUSION_POINTERS_RELATIVE_OFFSET + 2 * card_id;
+        let low_byte: usize = table[pointer_offset].into();
+        let high_byte: usize = table[pointer_offset + 1].into();
+        let list_relative_offset = (high_byte << 8) + low_byte;
+
+        if list_relative_offset == 0 {
+            fusions.push(Vec::new());
+        }
+        else {
+            fusions.push(read_fusion_list(&table[list_relative_offset..]));
+        }
+    }
+
+    return FusionTable { fusions };
+}
+
+/// Encode a card id as a 10 bit card number, returning the top 2 bits
+/// and the low 8 bits.
+fn encode_card_id(card_id: usize) -> (u8, u8) {
+    assert!(card_id < NUMBER_OF_CARDS);
+    let card_number = card_id + 1;
+    return ((card_number >> 8) as u8, card_number as u8);
+}
+
+/// Encode a single card's list of fusions, including its count.
+fn encode_fusion_list(
+    card_id: usize,
+    fusions: &[Fusion],
+) -> Result<Vec<u8>, FusionTableError> {
+    let count = fusions.len();
+    if count > MAX_COUNT {
+        return Err(FusionTableError::TooManyFusions {
+            card_number: card_id + 1,
+            count,
+        });
+    }
+
+    let mut list_data = Vec::new();
+    if count > MAX_SHORT_COUNT {
+        list_data.push(0);
+        list_data.push((MAX_COUNT - count) as u8);
+    }
+    else {
+        list_data.push(count as u8);
+    }
+
+    for pair in fusions.chunks(2) {
+        let (partner_1_high, partner_1_low) = encode_card_id(pair[0].partner);
+        let (result_1_high, result_1_low) = encode_card_id(pair[0].result);
+        // The second half of the last group is left empty if there is
+        // an odd number of fusions.
+        let ((partner_2_high, partner_2_low), (result_2_high, result_2_low)) =
+            match pair.get(1) {
+                Some(fusion) => (
+                    encode_card_id(fusion.partner),
+                    encode_card_id(fusion.result),
+                ),
+                None => ((0, 0), (0, 0)),
+            };
+
+        list_data.push(
+            partner_1_high
+                | result_1_high << 2
+                | partner_2_high << 4
+                | result_2_high << 6,
+        );
+        list_data.push(partner_1_low);
+        list_data.push(result_1_low);
+        list_data.push(partner_2_low);
+        list_data.push(result_2_low);
+    }
+
+    return Ok(list_data);
+}
+
+/// Write the fusion table into the given wa_mrg file. The lists are
+/// laid out one after the other, right after the pointers. Only the
+/// bytes that the new table takes up are written: we don't know whether
+/// the game uses the rest of `FUSION_TABLE_SIZE`, so it keeps its
+/// original bytes.
+///
+/// The table is left untouched if it is the same as the one in the
+/// file, so that the original layout is kept when there is nothing to
+/// change.
+///
+/// Fails if the encoded table doesn't fit in `FUSION_TABLE_SIZE`,
+/// before writing anything.
+pub fn write_fusion_table(
+    wa_mrg: &mut WaMrgArchive,
+    fusion_table: &FusionTable,
+) -> Result<(), FusionTableError> {
+    assert!(fusion_table.fusions.len() == NUMBER_OF_CARDS);
+    if read_fusion_table(wa_mrg) == *fusion_table {
+        return Ok(());
+    }
+
+    let mut list_relative_offsets = Vec::new();
+    let mut lists_data = Vec::new();
+    for (card_id, fusions) in fusion_table.fusions.iter().enumerate() {
+        if fusions.is_empty() {
+            list_relative_offsets.push(0);
+            continue;
+        }
+
+        list_relative_offsets
+            .push(FUSION_LISTS_RELATIVE_OFFSET + lists_data.len());
+        lists_data.extend(encode_fusion_list(card_id, fusions)?);
+    }
+
+    let table_size = FUSION_LISTS_RELATIVE_OFFSET + lists_data.len();
+    if table_size > FUSION_TABLE_SIZE {
+        return Err(FusionTableError::TooBig { size: table_size });
+    }
+
+    let table = &mut wa_mrg.as_bytes_mut()
+        [FUSION_TABLE_OFFSET..FUSION_TABLE_OFFSET + FUSION_TABLE_SIZE];
+    for (card_id, list_relative_offset) in
+        list_relative_offsets.into_iter().enumerate()
+    {
+        let pointer_offset = FUSION_POINTERS_RELATIVE_OFFSET + 2 * card_id;
+        table[pointer_offset] = list_relative_offset as u8;
+        table[pointer_offset + 1] = (list_relative_offset >> 8) as u8;
+    }
+    table[FUSION_LISTS_RELATIVE_OFFSET..table_size]
+        .copy_from_slice(&lists_data);
+
+    return Ok(());
+}
+
+/// Dump the fusion table into a .csv file at the given path, with a row
+/// per fusion:
+///
+/// card_id,partner_id,result_id,card_name,partner_name,result_name
+///
+/// The names are only there to help the user.
+pub fn dump_fusions_csv(
+    csv_path: &std::path::Path,
+    fusion_table: &FusionTable,
+    card_names: &[String],
+) {
+    let mut csv = Writer::from_path(csv_path).unwrap();
+    csv.write_record([
+        "card_id",
+        "partner_id",
+        "result_id",
+        "card_name",
+        "partner_name",
+        "result_name",
+    ])
+    .unwrap();
+
+    for (card_id, fusions) in fusion_table.fusions.iter().enumerate() {
+        for fusion in fusions {
+            // Shift the ids by 1 to match the official numbers which
+            // start at 1.
+            csv.write_record([
+                &(card_id + 1).to_string(),
+                &(fusion.partner + 1).to_string(),
+                &(fusion.result + 1).to_string(),
+                &card_names[card_id],
+                &card_names[fusion.partner],
+                &card_names[fusion.result],
+            ])
+            .unwrap();
+        }
+    }
+}
+
+/// Parse an official card number from a field of fusions.csv and
+/// convert it to a card id.
+fn parse_card_number(csv_path: &std::path::Path, field: &str) -> usize {
+    let card_number = field.parse::<usize>().unwrap_or(0);
+    assert!(
+        (1..=NUMBER_OF_CARDS).contains(&card_number),
+        "Invalid card number \"{field}\" in {}",
+        csv_path.display()
+    );
+    return card_number - 1;
+}
+
+/// Load fusions from a .csv file at the given path, in the format
+/// written by `dump_fusions_csv`, and update `fusion_table` with them.
+/// Like the duelist csv files this one is sparse: the file might be
+/// missing, and the fusions of the cards that don't appear in it are
+/// kept as they are. The fusions of each card that does appear are
+/// replaced by its rows. To remove every fusion of a card, give it a
+/// single row with an empty partner and result.
+pub fn load_fusions_csv(
+    csv_path: &std::path::Path,
+    fusion_table: &mut FusionTable,
+) {
+    if !csv_path.try_exists().unwrap() {
+        return;
+    }
+
+    let mut csv = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(csv_path)
+        .unwrap();
+
+    let mut loaded_cards = vec![false; NUMBER_OF_CARDS];
+    for record_result in csv.records() {
+        let record = record_result.unwrap();
+        let card_id = parse_card_number(csv_path, record.get(0).unwrap());
+        let partner = record.get(1).unwrap_or("");
+        let result = record.get(2).unwrap_or("");
+
+        // Forget the card's fusions from the ROM the first time we see
+        // it, and add every fusion that follows.
+        let fusions = &mut fusion_table.fusions[card_id];
+        if !loaded_cards[card_id] {
+            fusions.clear();
+            loaded_cards[card_id] = true;
+        }
+
+        if partner.is_empty() && result.is_empty() {
+            continue;
+        }
+        fusions.push(Fusion {
+            partner: parse_card_number(csv_path, partner),
+            result: parse_card_number(csv_path, result),
+        });
+    }
+}
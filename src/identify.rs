@@ -0,0 +1,200 @@
+//! Identify which release of the game an image contains, and hash it
+//! so that it can be compared with the dumps listed in dump databases.
+//!
+//! The release is found from the BOOT line of `system.cnf`, which names
+//! the executable the console starts, eg `cdrom:\SLUS_014.11;1`. Each
+//! release of the game has its own executable name. Different revisions
+//! of the same release would share the name, so they can only be told
+//! apart by hashing the image. We don't have a list of verified hashes
+//! to compare with yet, so that is up to the user.
+//!
+//! All hashes are CRC-32, the same checksum that dump databases such as
+//! redump.org list for each track. The image is hashed as raw 2352 byte
+//! sectors, so for a .bin image it is the checksum of the data track.
+
+use std::io::{Read, Seek};
+
+use crate::image::{self, RomImage};
+use crate::iso9660;
+
+const SYSTEM_CNF_PATH: &str = "system.cnf";
+
+/// The executable of the release fmde supports. All offsets in the
+/// other modules are only valid for this release.
+pub const SUPPORTED_EXECUTABLE: &str = "SLUS_014.11";
+
+const CRC_32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// A release of the game, as identified by its executable.
+pub struct Release {
+    /// Name of the executable as it appears in the BOOT line, without
+    /// the version suffix.
+    pub executable: &'static str,
+
+    pub title: &'static str,
+
+    pub region: &'static str,
+
+    pub language: &'static str,
+}
+
+const RELEASES: &[Release] = &[
+    Release {
+        executable: "SLUS_014.11",
+        title: "Yu-Gi-Oh! Forbidden Memories",
+        region: "NTSC-U",
+        language: "English",
+    },
+    Release {
+        executable: "SLES_039.47",
+        title: "Yu-Gi-Oh! Forbidden Memories",
+        region: "PAL",
+        language: "English",
+    },
+    Release {
+        executable: "SLES_039.48",
+        title: "Yu-Gi-Oh! Forbidden Memories",
+        region: "PAL",
+        language: "French",
+    },
+    Release {
+        executable: "SLES_039.49",
+        title: "Yu-Gi-Oh! Forbidden Memories",
+        region: "PAL",
+        language: "German",
+    },
+    Release {
+        executable: "SLES_039.50",
+        title: "Yu-Gi-Oh! Forbidden Memories",
+        region: "PAL",
+        language: "Italian",
+    },
+    Release {
+        executable: "SLES_039.51",
+        title: "Yu-Gi-Oh! Forbidden Memories",
+        region: "PAL",
+        language: "Spanish",
+    },
+    Release {
+        executable: "SLPM_863.98",
+        title: "Yu-Gi-Oh! Shin Duel Monsters",
+        region: "NTSC-J",
+        language: "Japanese",
+    },
+];
+
+/// Everything we could find out about an image.
+pub struct Identification {
+    /// Executable named by the BOOT line of `system.cnf`, if the image
+    /// has one.
+    pub executable: Option<String>,
+
+    pub release: Option<&'static Release>,
+
+    pub image_crc: u32,
+
+    /// Hashes of the game files. Only calculated for the supported
+    /// release, the other releases store them under different names.
+    pub slus_crc: Option<u32>,
+    pub wa_mrg_crc: Option<u32>,
+}
+
+/// Extract the name of the executable from the contents of
+/// `system.cnf`, eg `SLUS_014.11` from the line
+/// `BOOT = cdrom:\SLUS_014.11;1`.
+fn parse_boot_executable(system_cnf: &str) -> Option<String> {
+    for line in system_cnf.lines() {
+        let Some((key, value)) = line.split_once('=')
+        else {
+            continue;
+        };
+        if !key.trim().eq_ignore_ascii_case("BOOT") {
+            continue;
+        }
+
+        let path = value.trim();
+        let name = path.rsplit(['\\', '/', ':']).next().unwrap();
+        let name = name.split(';').next().unwrap();
+        return Some(name.to_uppercase());
+    }
+
+    return None;
+}
+
+/// Read the name of the executable the console boots from the image.
+pub fn read_boot_executable<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+) -> Option<String> {
+    let entry = iso9660::find_file(rom, SYSTEM_CNF_PATH)?;
+    let system_cnf = image::read_file(rom, &entry);
+    return parse_boot_executable(&String::from_utf8_lossy(&system_cnf));
+}
+
+/// Find the release of the game that an executable belongs to.
+pub fn find_release(executable: &str) -> Option<&'static Release> {
+    return RELEASES
+        .iter()
+        .find(|release| release.executable == executable);
+}
+
+/// Panics with a helpful message unless the image contains the release
+/// that fmde supports. Every command that reads or writes game data
+/// should call this first, because on any other release the hardcoded
+/// offsets point to unrelated data.
+pub fn check_supported_release<F: Read + Seek>(rom: &mut RomImage<F>) {
+    let executable = read_boot_executable(rom);
+    if executable.as_deref() == Some(SUPPORTED_EXECUTABLE) {
+        return;
+    }
+
+    let description = match executable.as_deref().map(find_release) {
+        Some(Some(release)) => format!(
+            "the {} {} release ({})",
+            release.region, release.language, release.executable
+        ),
+        Some(None) => {
+            format!("an unknown game ({})", executable.as_ref().unwrap())
+        }
+        None => "not a PlayStation game".to_string(),
+    };
+    panic!(
+        "The ROM is {description}. Only the NTSC-U release \
+         ({SUPPORTED_EXECUTABLE}) is supported."
+    );
+}
+
+/// Calculate the CRC-32 of the whole data track as raw sectors.
+fn hash_image<F: Read + Seek>(rom: &mut RomImage<F>) -> u32 {
+    let mut digest = CRC_32.digest();
+    for sector_index in 0..rom.sector_count() {
+        digest.update(&rom.read_sector(sector_index));
+    }
+    return digest.finalize();
+}
+
+/// Identify the release in the image and hash it.
+pub fn identify_image<F: Read + Seek>(rom: &mut RomImage<F>) -> Identification {
+    let executable = read_boot_executable(rom);
+    let release = executable.as_deref().and_then(find_release);
+
+    let (slus_crc, wa_mrg_crc) =
+        if executable.as_deref() == Some(SUPPORTED_EXECUTABLE) {
+            let slus = image::read_slus_from_bin(rom);
+            let wa_mrg = image::read_wa_mrg_from_bin(rom);
+            (
+                Some(CRC_32.checksum(slus.as_bytes())),
+                Some(CRC_32.checksum(wa_mrg.as_bytes())),
+            )
+        }
+        else {
+            (None, None)
+        };
+
+    return Identification {
+        executable,
+        release,
+        image_crc: hash_image(rom),
+        slus_crc,
+        wa_mrg_crc,
+    };
+}
@@ -3,183 +3,672 @@
 //! - slus_014.11
 //! - data/wa_mrg.mrg
 //!
-//! The format seems to be CD-ROM/XA Form 1. I don't know exactly how it
-//! works but it seems to have the following layout:
-//! - 12 bytes sync pattern
-//! - 3 bytes address
-//! - 1 byte mode
-//! - 8 bytes subheader
-//! - 2048 bytes data
-//! - 4 bytes error detection
-//! - 276 bytes error correction
+//! Their locations are looked up in the ISO-9660 filesystem of the data
+//! track, see the `iso9660` module. The layout of each sector is
+//! described in the `sector` module.
 //!
-//! I'm wondering if emulators even check the error detection and
-//! correction. It's possible that we can ignore them and work solely on
-//! the data.
+//! The image is accessed one sector at a time through `Read + Seek`,
+//! so we never have to hold the whole ~700MB image in memory. Only the
+//! sectors we actually need are read, and when writing a modified ROM
+//! the rest of the image is copied through unchanged.
 
-// TODO: Implement a stronger typing system. In the end we are going to
-// have at least three different objects that are all basically &[u8],
-// and we should prevent getting them mixed up. For example a function
-// that wants to operate on the SLUS file should not accept the entire
-// ROM. Even just some types that do nothing more than wrap the &[u8]
-// would be an improvement of the interface.
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
 
-use crc;
+use crate::overlay::Overlay;
+use crate::sector::{
+    self, DATA_SIZE_BYTES, MODE2_SIZE_BYTES, SECTOR_SIZE_BYTES,
+};
+use crate::{card, cue, duelist, iso9660, rebuild};
 
-const SECTOR_SIZE_BYTES: usize = 2352;
+pub const SLUS_PATH: &str = "slus_014.11";
+pub const WA_MRG_PATH: &str = "data/wa_mrg.mrg";
 
-const DATA_OFFSET_BYTES: usize = 24;
-const DATA_SIZE_BYTES: usize = 2048;
+/// Every PS1 executable starts with this signature.
+const SLUS_SIGNATURE: &[u8] = b"PS-X EXE";
 
-const CRC_OFFSET_BYTES: usize = 2072;
+/// End of the last table we access in each file. Anything shorter is
+/// not the file we are looking for.
+const SLUS_MIN_SIZE_BYTES: usize =
+    duelist::DUELIST_NAME_INDICES_OFFSET + 2 * duelist::NUMBER_OF_DUELISTS;
+const WA_MRG_MIN_SIZE_BYTES: usize =
+    card::STARCHIPS_OFFSET + card::STARCHIPS_SIZE * duelist::NUMBER_OF_CARDS;
 
-const SLUS_OFFSET_SECTORS: usize = 24;
-const SLUS_SIZE_SECTORS: usize = 929;
+/// The contents of `SLUS_014.11`, the game's executable. It holds the
+/// card stats and all the text of the game.
+pub struct SlusExecutable {
+    data: Vec<u8>,
+}
 
-const WA_MRG_OFFSET_SECTORS: usize = 10102;
-const WA_MRG_SIZE_SECTORS: usize = 18432;
+impl SlusExecutable {
+    /// Wrap the contents of the executable. Panics if `data` is not a
+    /// PS1 executable or is too small to contain the game's tables.
+    pub fn new(data: Vec<u8>) -> SlusExecutable {
+        assert!(
+            data.starts_with(SLUS_SIGNATURE),
+            "Not a PS1 executable, missing the PS-X EXE signature"
+        );
+        assert!(
+            data.len() >= SLUS_MIN_SIZE_BYTES,
+            "Executable is too small to be SLUS_014.11 ({} bytes)",
+            data.len()
+        );
 
-fn calculate_crc(raw_data: &[u8]) -> u32 {
-    assert!(raw_data.len() == DATA_SIZE_BYTES + 8);
+        return SlusExecutable { data };
+    }
 
-    const CUSTOM_ALG: crc::Algorithm<u32> = crc::Algorithm {
-        width: 32,
-        poly: 0x8001801b,
-        init: 0x0,
-        refin: true,
-        refout: true,
-        xorout: 0x0,
-        // I'm not sure what these last two are, but they don't seem to
-        // be part of the calculation. They are probably either for
-        // testing or for use on the receiving side.
-        check: 0x0,
-        residue: 0x0,
-    };
+    pub fn as_bytes(&self) -> &[u8] {
+        return &self.data;
+    }
 
-    let crc = crc::Crc::<u32>::new(&CUSTOM_ALG);
-    let mut digest = crc.digest();
-    digest.update(raw_data);
-    return digest.finalize();
+    /// The contents can be modified but not resized, so that the checks
+    /// made by `new` still hold.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        return &mut self.data;
+    }
 }
 
-/// Extract `SLUS-014.11` from the bin file. This conversion throws away
-/// all the metadata required by CD-ROM/XA and returns a concatenated
-/// vector of the raw data.
-pub fn read_slus_from_bin(rom_file: &Vec<u8>) -> Vec<u8> {
-    let start = SLUS_OFFSET_SECTORS * SECTOR_SIZE_BYTES;
-    let end = start + SLUS_SIZE_SECTORS * SECTOR_SIZE_BYTES;
-    return cdxa_form1_to_raw_data(&rom_file[start..end]);
+/// The contents of `WA_MRG.MRG`, the archive with most of the game's
+/// data, eg the duelists' decks and drops. It has no header, so we can
+/// only check its size.
+pub struct WaMrgArchive {
+    data: Vec<u8>,
 }
 
-/// Write `SLUS-014.11` into the bin file. This doesn't touch the
-/// CD-ROM/XA metadata that is already in the bin file. This would
-/// probably make it break on real hardware because it would
-/// detect corrupted data, but it's probably OK for emulators.
-pub fn write_slus_to_bin(rom_file: &mut Vec<u8>, slus: &[u8]) {
-    assert!(slus.len() == SLUS_SIZE_SECTORS * DATA_SIZE_BYTES);
+impl WaMrgArchive {
+    /// Wrap the contents of the archive. Panics if `data` is too small
+    /// to contain the game's tables.
+    pub fn new(data: Vec<u8>) -> WaMrgArchive {
+        assert!(
+            data.len() >= WA_MRG_MIN_SIZE_BYTES,
+            "Archive is too small to be WA_MRG.MRG ({} bytes)",
+            data.len()
+        );
 
-    let start = SLUS_OFFSET_SECTORS * SECTOR_SIZE_BYTES;
-    let end = start + SLUS_SIZE_SECTORS * SECTOR_SIZE_BYTES;
+        return WaMrgArchive { data };
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        return &self.data;
+    }
 
-    raw_data_to_cdxa_form1(&slus, &mut rom_file[start..end]);
+    /// The contents can be modified but not resized, so that the checks
+    /// made by `new` still hold.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        return &mut self.data;
+    }
 }
 
-/// Extract `WA_MRG.MRG` from the bin file. This conversion throws away
-/// all the metadata required by CD-ROM/XA and returns a concatenated
-/// vector of the raw data.
-pub fn read_wa_mrg_from_bin(rom_file: &Vec<u8>) -> Vec<u8> {
-    let start = WA_MRG_OFFSET_SECTORS * SECTOR_SIZE_BYTES;
-    let end = start + WA_MRG_SIZE_SECTORS * SECTOR_SIZE_BYTES;
-    return cdxa_form1_to_raw_data(&rom_file[start..end]);
+/// The layouts a disc image can be stored in. Internally we always
+/// work with raw 2352 byte sectors, and convert from and to the other
+/// layouts when reading and writing sectors.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SectorFormat {
+    /// Whole 2352 byte sectors as read from the disc, usually in a .bin
+    /// file.
+    Raw,
+
+    /// 2336 byte Mode 2 sectors, ie without the sync pattern and the
+    /// header.
+    Mode2,
+
+    /// Only the 2048 bytes of data of each sector, usually in an .iso
+    /// file. Form 2 sectors don't fit in this layout, so streamed audio
+    /// and video are truncated.
+    Iso,
 }
 
-/// Write `WA_MRG.MRG` into the bin file. This doesn't touch the
-/// CD-ROM/XA metadata that is already in the bin file. This would
-/// probably make it break on real hardware because it would
-/// detect corrupted data, but it's probably OK for emulators.
-pub fn write_wa_mrg_to_bin(rom_file: &mut Vec<u8>, wa_mrg: &[u8]) {
-    assert!(wa_mrg.len() == WA_MRG_SIZE_SECTORS * DATA_SIZE_BYTES);
+impl SectorFormat {
+    pub fn sector_size(&self) -> usize {
+        return match self {
+            SectorFormat::Raw => SECTOR_SIZE_BYTES,
+            SectorFormat::Mode2 => MODE2_SIZE_BYTES,
+            SectorFormat::Iso => DATA_SIZE_BYTES,
+        };
+    }
 
-    let start = WA_MRG_OFFSET_SECTORS * SECTOR_SIZE_BYTES;
-    let end = start + WA_MRG_SIZE_SECTORS * SECTOR_SIZE_BYTES;
+    /// Offset of the raw sector at which the sectors of this layout
+    /// start.
+    fn raw_offset(&self) -> usize {
+        return match self {
+            SectorFormat::Raw => 0,
+            SectorFormat::Mode2 => sector::MODE2_OFFSET_BYTES,
+            SectorFormat::Iso => sector::DATA_OFFSET_BYTES,
+        };
+    }
 
-    raw_data_to_cdxa_form1(&wa_mrg, &mut rom_file[start..end]);
+    /// The track mode used for this layout in cue sheets. Plain .iso
+    /// files are normally used without a cue sheet.
+    fn cue_mode(&self) -> Option<&'static str> {
+        return match self {
+            SectorFormat::Raw => Some("MODE2/2352"),
+            SectorFormat::Mode2 => Some("MODE2/2336"),
+            SectorFormat::Iso => None,
+        };
+    }
+
+    fn from_cue_mode(mode: &str) -> SectorFormat {
+        return match mode {
+            "MODE2/2352" => SectorFormat::Raw,
+            "MODE2/2336" => SectorFormat::Mode2,
+            "MODE1/2048" | "MODE2/2048" => SectorFormat::Iso,
+            _ => panic!("Unsupported track mode {mode}"),
+        };
+    }
 }
 
-/// Get the raw data from a single CD-ROM/XA Form 1 sector. Usually you
-/// don't want to operate on a single sector. This function's main
-/// intent is to be called by other functions that will work on spans of
-/// sectors.
-fn read_data_from_sector(sector: &[u8]) -> &[u8] {
-    assert!(sector.len() == SECTOR_SIZE_BYTES);
-    return &sector[DATA_OFFSET_BYTES..DATA_OFFSET_BYTES + DATA_SIZE_BYTES];
+/// Figure out the layout of an image from the first few sectors of its
+/// contents. Raw sectors start with the sync pattern. For the other
+/// layouts we look for the primary volume descriptor at sector 16.
+pub fn detect_sector_format(image_start: &[u8]) -> SectorFormat {
+    if image_start.starts_with(&sector::SYNC_PATTERN) {
+        return SectorFormat::Raw;
+    }
+
+    for format in [SectorFormat::Iso, SectorFormat::Mode2] {
+        let offset = 16 * format.sector_size() + sector::DATA_OFFSET_BYTES
+            - format.raw_offset()
+            + 1;
+        if image_start.get(offset..offset + 5) == Some(b"CD001") {
+            return format;
+        }
+    }
+
+    panic!("Unrecognized image format");
 }
 
-/// Write the raw data into a single CD-ROM/XA Form 1 sector. This only
-/// writes the payload into the sector and leaves all the metadata
-/// zeroed out.
-///
-/// If the raw data is smaller than the payload size of the sector, the
-/// rest will be zeroed out. If the raw data is larger, the function
-/// panics.
-///
-/// Usually you don't want to operate on a single sector. This
-/// function's main intent is to be called by other functions that will
-/// work on spans of sectors.
-fn write_data_to_sector(raw_data: &[u8], sector: &mut [u8]) {
-    assert!(raw_data.len() <= DATA_SIZE_BYTES);
-    assert!(sector.len() == SECTOR_SIZE_BYTES);
+/// The data track of a disc image, stored in any of the supported
+/// layouts. `F` is where the image is stored, usually a `File`. All
+/// sector indices are relative to the start of the data track.
+pub struct RomImage<F> {
+    file: F,
+    format: SectorFormat,
 
-    sector[DATA_OFFSET_BYTES..DATA_OFFSET_BYTES + DATA_SIZE_BYTES]
-        .copy_from_slice(raw_data);
+    /// Byte offset of the data track in `file`, eg to skip a pregap.
+    track_offset: u64,
 
-    let crc_segment = &sector[16..DATA_OFFSET_BYTES + DATA_SIZE_BYTES];
-    let crc = calculate_crc(crc_segment);
+    sector_count: usize,
+}
 
-    sector[CRC_OFFSET_BYTES] = crc as u8;
-    sector[CRC_OFFSET_BYTES + 1] = (crc >> 8) as u8;
-    sector[CRC_OFFSET_BYTES + 2] = (crc >> 16) as u8;
-    sector[CRC_OFFSET_BYTES + 3] = (crc >> 24) as u8;
+pub fn is_cue_path(path: &std::path::Path) -> bool {
+    return path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("cue"));
 }
 
-/// Extract and concatenate the raw data from a slice of CD-ROM/XA Form
-/// 1 data. `cdxa_data` must be comprised of whole sectors, ie its size
-/// must be (2352 * n) bytes.
-fn cdxa_form1_to_raw_data(cdxa_data: &[u8]) -> Vec<u8> {
-    assert!(
-        cdxa_data.len() % SECTOR_SIZE_BYTES == 0,
-        "cdxa_data must be whole sectors"
-    );
+impl RomImage<std::fs::File> {
+    /// Open the data track of a ROM for reading.
+    ///
+    /// `rom_path` can be either a cue sheet or an image file. For a cue
+    /// sheet, the data track is looked up and only its sectors are
+    /// used, without any pregap or other tracks stored in the same
+    /// file. An image file is assumed to contain only the data track,
+    /// and its layout is detected from its contents.
+    ///
+    /// Panics if the data track doesn't contain an ISO-9660 filesystem.
+    pub fn open(rom_path: &std::path::Path) -> RomImage<std::fs::File> {
+        if !is_cue_path(rom_path) {
+            let file =
+                std::fs::File::open(rom_path).expect("Failed to load file");
+            return RomImage::from_file(file);
+        }
 
-    let mut raw_data = Vec::new();
+        let cue_sheet = cue::load_cue_sheet(rom_path);
+        let track = cue_sheet.find_data_track(rom_path.parent().unwrap());
+        let format = SectorFormat::from_cue_mode(&track.mode);
+        if track.has_other_tracks {
+            eprintln!(
+                "{} has more than one track. Only the data track will be \
+                 used.",
+                rom_path.display()
+            );
+        }
 
-    for i in 0..cdxa_data.len() / SECTOR_SIZE_BYTES {
-        let start = i * SECTOR_SIZE_BYTES;
-        let end = start + SECTOR_SIZE_BYTES;
-        let sector = &cdxa_data[start..end];
-        raw_data.extend_from_slice(read_data_from_sector(&sector));
+        let file =
+            std::fs::File::open(&track.bin_path).expect("Failed to load file");
+        let end_offset =
+            track.end_offset.unwrap_or(file.metadata().unwrap().len());
+
+        let mut rom = RomImage {
+            file,
+            format,
+            track_offset: track.start_offset,
+            sector_count: (end_offset - track.start_offset) as usize
+                / format.sector_size(),
+        };
+        rom.check_filesystem();
+        return rom;
     }
 
-    return raw_data;
+    /// Create a new, empty image to write a ROM in the given layout.
+    /// Raw and Mode 2 images are written as a .bin file along with a
+    /// cue sheet that describes it, so that they can be loaded
+    /// directly by emulators. `output_path` can name either of the
+    /// two. The other one is written next to it with the same name
+    /// and the `.bin` or `.cue` extension. Iso images are written
+    /// without a cue sheet.
+    ///
+    /// Panics if the output file already exists.
+    pub fn create(
+        output_path: &std::path::Path,
+        format: SectorFormat,
+    ) -> RomImage<std::fs::File> {
+        let bin_path = if is_cue_path(output_path) {
+            match format {
+                SectorFormat::Iso => output_path.with_extension("iso"),
+                _ => output_path.with_extension("bin"),
+            }
+        }
+        else {
+            output_path.to_path_buf()
+        };
+
+        let file = std::fs::File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&bin_path)
+            .unwrap();
+
+        if let Some(cue_mode) = format.cue_mode() {
+            let bin_name = bin_path.file_name().unwrap().to_string_lossy();
+            cue::dump_cue_sheet(
+                &bin_path.with_extension("cue"),
+                &cue::CueSheet::single_track(&bin_name, cue_mode),
+            );
+        }
+
+        return RomImage {
+            file,
+            format,
+            track_offset: 0,
+            sector_count: 0,
+        };
+    }
 }
 
-/// Write raw data into a slice of CD-ROM/XA Form 1 data. Doesn't touch
-/// the metadata. `cdxa_data` must be comprised of whole sectors, ie its
-/// size must be (2352 * n) bytes.
-fn raw_data_to_cdxa_form1(raw_data: &[u8], cdxa_data: &mut [u8]) {
-    assert!(
-        cdxa_data.len() % SECTOR_SIZE_BYTES == 0,
-        "cdxa_data must be whole sectors"
-    );
-    assert!(
-        raw_data.len() / DATA_SIZE_BYTES <= cdxa_data.len() / SECTOR_SIZE_BYTES,
-        "raw data doesn't fit into cdxa data"
-    );
+impl<F: Read + Seek> RomImage<F> {
+    /// Use an image that only contains the data track, eg an opened
+    /// .bin file or an in-memory `Cursor`. The layout is detected from
+    /// its contents. Panics if the layout can't be detected or the data
+    /// track doesn't contain an ISO-9660 filesystem.
+    pub fn from_file(mut file: F) -> RomImage<F> {
+        let file_size = file.seek(SeekFrom::End(0)).unwrap() as usize;
+
+        let mut image_start = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        (&mut file)
+            .take(17 * SECTOR_SIZE_BYTES as u64)
+            .read_to_end(&mut image_start)
+            .unwrap();
+        let format = detect_sector_format(&image_start);
+
+        let mut rom = RomImage {
+            file,
+            format,
+            track_offset: 0,
+            sector_count: file_size / format.sector_size(),
+        };
+        rom.check_filesystem();
+        return rom;
+    }
+
+    /// Panics if the data track doesn't contain a filesystem. This
+    /// catches eg a cue sheet pointing at the wrong track, or a file
+    /// that merely starts with a sync pattern.
+    fn check_filesystem(&mut self) {
+        assert!(
+            iso9660::has_primary_volume_descriptor(self),
+            "No ISO-9660 filesystem found in the data track"
+        );
+    }
+
+    /// Keep every write to the image in memory instead of writing it to
+    /// the file, see `Overlay`.
+    pub fn overlay(self) -> RomImage<Overlay<F>> {
+        return RomImage {
+            file: Overlay::new(self.file),
+            format: self.format,
+            track_offset: self.track_offset,
+            sector_count: self.sector_count,
+        };
+    }
+
+    /// Get back the file the image is stored in.
+    pub fn into_file(self) -> F {
+        return self.file;
+    }
+
+    pub fn format(&self) -> SectorFormat {
+        return self.format;
+    }
+
+    /// Number of sectors in the data track.
+    pub fn sector_count(&self) -> usize {
+        return self.sector_count;
+    }
+
+    /// Read a sector as it is stored in the image, ie in the layout of
+    /// the image.
+    fn read_stored_sector(&mut self, sector_index: usize) -> Vec<u8> {
+        assert!(sector_index < self.sector_count, "Sector out of range");
+
+        let sector_size = self.format.sector_size();
+        let mut stored_sector = vec![0; sector_size];
+        self.file
+            .seek(SeekFrom::Start(
+                self.track_offset + (sector_index * sector_size) as u64,
+            ))
+            .unwrap();
+        self.file.read_exact(&mut stored_sector).unwrap();
+        return stored_sector;
+    }
 
-    let raw_data_chunks = raw_data.chunks(DATA_SIZE_BYTES);
-    let cdxa_chunks = cdxa_data.chunks_mut(SECTOR_SIZE_BYTES);
+    /// Read a whole 2352 byte sector. For layouts that don't store the
+    /// whole sector, the missing parts are generated. Iso sectors
+    /// become Form 1 data sectors, because we can't know what they
+    /// were originally.
+    pub fn read_sector(&mut self, sector_index: usize) -> Vec<u8> {
+        let stored_sector = self.read_stored_sector(sector_index);
 
-    for (raw_data_chunk, current_sector) in raw_data_chunks.zip(cdxa_chunks) {
-        write_data_to_sector(raw_data_chunk, current_sector);
+        return match self.format {
+            SectorFormat::Raw => stored_sector,
+            SectorFormat::Mode2 => {
+                sector::build_mode2_sector(sector_index, &stored_sector)
+            }
+            SectorFormat::Iso => sector::build_form1_sector(
+                sector_index,
+                sector::SUBMODE_DATA,
+                &stored_sector,
+            ),
+        };
     }
+
+    /// Read a whole 2352 byte sector without regenerating any of the
+    /// parts stored in the image, so that it can be checked with
+    /// `sector::check_sector`. Mode 2 images don't store the sync
+    /// pattern and the header, so only those are generated.
+    ///
+    /// Panics for iso images, which don't store any of the parts that
+    /// can be checked.
+    pub fn read_sector_as_stored(&mut self, sector_index: usize) -> Vec<u8> {
+        let stored_sector = self.read_stored_sector(sector_index);
+
+        match self.format {
+            SectorFormat::Raw => return stored_sector,
+            SectorFormat::Mode2 => {
+                let mut raw_sector = vec![0; SECTOR_SIZE_BYTES];
+                sector::write_sector_header(&mut raw_sector, sector_index);
+                raw_sector[sector::MODE2_OFFSET_BYTES..]
+                    .copy_from_slice(&stored_sector);
+                return raw_sector;
+            }
+            SectorFormat::Iso => {
+                panic!("Iso images don't store the EDC and ECC of sectors")
+            }
+        }
+    }
+
+    /// Read the 2048 bytes of data of a Form 1 sector.
+    pub fn read_sector_data(&mut self, sector_index: usize) -> Vec<u8> {
+        let stored_sector = self.read_stored_sector(sector_index);
+        let data_offset = sector::DATA_OFFSET_BYTES - self.format.raw_offset();
+        return stored_sector[data_offset..][..DATA_SIZE_BYTES].to_vec();
+    }
+}
+
+impl<F: Read + Write + Seek> RomImage<F> {
+    /// Write a whole 2352 byte sector. Only the parts that are stored
+    /// in the layout of the image are written. Writing past the end of
+    /// the image extends it.
+    pub fn write_sector(&mut self, sector_index: usize, raw_sector: &[u8]) {
+        assert!(raw_sector.len() == SECTOR_SIZE_BYTES);
+
+        let sector_size = self.format.sector_size();
+        self.file
+            .seek(SeekFrom::Start(
+                self.track_offset + (sector_index * sector_size) as u64,
+            ))
+            .unwrap();
+        self.file
+            .write_all(&raw_sector[self.format.raw_offset()..][..sector_size])
+            .unwrap();
+
+        self.sector_count = self.sector_count.max(sector_index + 1);
+    }
+
+    /// Overwrite the data of a single Form 1 sector, eg to update a
+    /// directory record. The header and subheader are kept, the EDC and
+    /// ECC are regenerated.
+    pub fn write_sector_data(&mut self, sector_index: usize, raw_data: &[u8]) {
+        let mut raw_sector = self.read_sector(sector_index);
+        sector::write_data_to_sector(raw_data, &mut raw_sector);
+        self.write_sector(sector_index, &raw_sector);
+    }
+
+    /// Copy the whole data track into `output`, converting it to the
+    /// layout of `output`.
+    ///
+    /// When converting an iso image to one of the other layouts, the
+    /// last sector of each file is also marked as the end of the file,
+    /// like it would be on a real disc.
+    pub fn copy_to<W: Read + Write + Seek>(
+        &mut self,
+        output: &mut RomImage<W>,
+    ) {
+        if self.format == output.format {
+            let size = (self.sector_count * self.format.sector_size()) as u64;
+            self.file.seek(SeekFrom::Start(self.track_offset)).unwrap();
+            output
+                .file
+                .seek(SeekFrom::Start(output.track_offset))
+                .unwrap();
+            std::io::copy(&mut (&mut self.file).take(size), &mut output.file)
+                .unwrap();
+            output.sector_count = self.sector_count;
+            return;
+        }
+
+        for sector_index in 0..self.sector_count {
+            let raw_sector = self.read_sector(sector_index);
+            output.write_sector(sector_index, &raw_sector);
+        }
+
+        if self.format == SectorFormat::Iso {
+            mark_ends_of_files(output);
+        }
+    }
+}
+
+/// Mark the last sector of each file and of the volume descriptors as
+/// the end of a file. See `RomImage::copy_to`.
+fn mark_ends_of_files<F: Read + Write + Seek>(rom: &mut RomImage<F>) {
+    let mut last_sectors = iso9660::read_filesystem(rom)
+        .iter()
+        .filter(|entry| entry.size_sectors() > 0)
+        .map(|entry| entry.extent_sector + entry.size_sectors() - 1)
+        .collect::<Vec<usize>>();
+    last_sectors.push(iso9660::read_volume_descriptor_terminator(rom));
+
+    for sector_index in last_sectors {
+        let mut raw_sector = rom.read_sector(sector_index);
+        sector::mark_end_of_file(&mut raw_sector);
+        rom.write_sector(sector_index, &raw_sector);
+    }
+}
+
+/// Copy the data track of a ROM into a new image and return the new
+/// image, ready to be modified in place. The layout of the output is
+/// `format` if given, or the layout of the input otherwise. See
+/// `RomImage::open` and `RomImage::create` for details on the paths.
+pub fn copy_rom(
+    rom_path: &std::path::Path,
+    output_path: &std::path::Path,
+    format: Option<SectorFormat>,
+) -> RomImage<std::fs::File> {
+    let mut rom = RomImage::open(rom_path);
+    let mut output =
+        RomImage::create(output_path, format.unwrap_or(rom.format));
+    rom.copy_to(&mut output);
+    return output;
+}
+
+/// Find a file in the filesystem of the image. Panics if the file
+/// doesn't exist, because that means this is not an image we know how
+/// to handle.
+fn find_file_entry<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+    path: &str,
+) -> iso9660::FileEntry {
+    return iso9660::find_file(rom, path)
+        .unwrap_or_else(|| panic!("{path} not found in the ROM"));
+}
+
+/// Read a single sector of a file. For Form 1 files this is the raw
+/// data, truncated to the size of the file for the last sector. For
+/// Form 2 files it is the whole Mode 2 sector, see
+/// `iso9660::SectorForm`.
+pub fn read_file_sector<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+    entry: &iso9660::FileEntry,
+    index: usize,
+) -> Vec<u8> {
+    let sector_index = entry.extent_sector + index;
+
+    match entry.form {
+        iso9660::SectorForm::Form1 => {
+            let mut raw_data = rom.read_sector_data(sector_index);
+            raw_data.truncate(entry.size_bytes - index * DATA_SIZE_BYTES);
+            return raw_data;
+        }
+        iso9660::SectorForm::Form2 => {
+            let raw_sector = rom.read_sector(sector_index);
+            return raw_sector[sector::MODE2_OFFSET_BYTES..].to_vec();
+        }
+    }
+}
+
+/// Extract a file from the image. Form 1 files are returned as the
+/// concatenated raw data, with all the metadata required by CD-ROM/XA
+/// thrown away. Form 2 files are returned as whole Mode 2 sectors, see
+/// `iso9660::SectorForm`.
+pub fn read_file<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+    entry: &iso9660::FileEntry,
+) -> Vec<u8> {
+    let mut contents = Vec::new();
+    for index in 0..entry.size_sectors() {
+        contents.extend_from_slice(&read_file_sector(rom, entry, index));
+    }
+    return contents;
+}
+
+/// Extract a file from the image by its path.
+fn read_file_from_bin<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+    path: &str,
+) -> Vec<u8> {
+    let entry = find_file_entry(rom, path);
+    return read_file(rom, &entry);
+}
+
+/// Write the contents of a file into consecutive sectors starting at
+/// `extent_sector`, generating every part of each sector from scratch.
+///
+/// For Form 1, `contents` is the plain data of the file. Every sector
+/// is marked as data, and the last one is also marked as the end of the
+/// record and the file.
+///
+/// For Form 2, `contents` must be whole 2336 byte Mode 2 sectors as
+/// returned by `read_file`. Each sector carries its own subheader.
+pub fn write_file_sectors<F: Read + Write + Seek>(
+    rom: &mut RomImage<F>,
+    extent_sector: usize,
+    form: iso9660::SectorForm,
+    contents: &[u8],
+) {
+    match form {
+        iso9660::SectorForm::Form1 => {
+            let sector_count = contents.len().div_ceil(DATA_SIZE_BYTES);
+            for (i, chunk) in contents.chunks(DATA_SIZE_BYTES).enumerate() {
+                let mut submode = sector::SUBMODE_DATA;
+                if i == sector_count - 1 {
+                    submode |= sector::SUBMODE_END_OF_RECORD
+                        | sector::SUBMODE_END_OF_FILE;
+                }
+
+                let raw_sector = sector::build_form1_sector(
+                    extent_sector + i,
+                    submode,
+                    chunk,
+                );
+                rom.write_sector(extent_sector + i, &raw_sector);
+            }
+        }
+        iso9660::SectorForm::Form2 => {
+            assert!(
+                contents.len().is_multiple_of(MODE2_SIZE_BYTES),
+                "Form 2 files must be whole Mode 2 sectors"
+            );
+
+            for (i, chunk) in contents.chunks(MODE2_SIZE_BYTES).enumerate() {
+                let raw_sector =
+                    sector::build_mode2_sector(extent_sector + i, chunk);
+                rom.write_sector(extent_sector + i, &raw_sector);
+            }
+        }
+    }
+}
+
+/// Write a file into the image. If the file has the same size as the
+/// one it replaces, its sectors are overwritten in place, keeping the
+/// headers and subheaders already in the image and regenerating the EDC
+/// and ECC. Otherwise the image is rebuilt around the new file, see
+/// `rebuild::rebuild_image`.
+fn write_file_to_bin<F: Read + Write + Seek>(
+    rom: &mut RomImage<F>,
+    path: &str,
+    raw_data: &[u8],
+) {
+    let entry = find_file_entry(rom, path);
+
+    if raw_data.len() != entry.size_bytes {
+        let new_files = HashMap::from([(entry.path, raw_data.to_vec())]);
+        rebuild::rebuild_image(rom, &new_files);
+        return;
+    }
+
+    for (i, chunk) in raw_data.chunks(DATA_SIZE_BYTES).enumerate() {
+        rom.write_sector_data(entry.extent_sector + i, chunk);
+    }
+}
+
+/// Extract `SLUS-014.11` from the image.
+pub fn read_slus_from_bin<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+) -> SlusExecutable {
+    return SlusExecutable::new(read_file_from_bin(rom, SLUS_PATH));
+}
+
+/// Write `SLUS-014.11` into the image.
+pub fn write_slus_to_bin<F: Read + Write + Seek>(
+    rom: &mut RomImage<F>,
+    slus: &SlusExecutable,
+) {
+    write_file_to_bin(rom, SLUS_PATH, slus.as_bytes());
+}
+
+/// Extract `WA_MRG.MRG` from the image.
+pub fn read_wa_mrg_from_bin<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+) -> WaMrgArchive {
+    return WaMrgArchive::new(read_file_from_bin(rom, WA_MRG_PATH));
+}
+
+/// Write `WA_MRG.MRG` into the image.
+pub fn write_wa_mrg_to_bin<F: Read + Write + Seek>(
+    rom: &mut RomImage<F>,
+    wa_mrg: &WaMrgArchive,
+) {
+    write_file_to_bin(rom, WA_MRG_PATH, wa_mrg.as_bytes());
 }
@@ -0,0 +1,128 @@
+//! International Patching System (IPS), the oldest and simplest patch
+//! format. A patch is the signature `PATCH`, a list of records and the
+//! marker `EOF`. Each record is:
+//! - 3 bytes offset
+//! - 2 bytes size
+//! - the data to write at the offset, or if the size is zero a 2 byte
+//!   count and a single byte to repeat that many times
+//!
+//! All numbers are big-endian. Offsets are limited to 16MB, so IPS
+//! can't patch a whole disc image. We use it for patches of the SLUS
+//! file only. An offset that reads as `EOF` would end the patch early,
+//! so no record may start there.
+//!
+//! Some patches add 3 more bytes after `EOF` with the size to truncate
+//! the file to, which we support when applying them.
+
+pub const SIGNATURE: &[u8] = b"PATCH";
+const END_OF_FILE: &[u8] = b"EOF";
+
+const MAX_OFFSET: usize = 0xFFFFFF;
+const MAX_RECORD_SIZE_BYTES: usize = 0xFFFF;
+
+/// The offset that can't be used by a record, because it is the same
+/// bytes as `EOF`.
+const END_OF_FILE_OFFSET: usize = 0x454F46;
+
+fn read_u24_be(data: &[u8], offset: usize) -> usize {
+    return (data[offset] as usize) << 16
+        | (data[offset + 1] as usize) << 8
+        | data[offset + 2] as usize;
+}
+
+fn read_u16_be(data: &[u8], offset: usize) -> usize {
+    return (data[offset] as usize) << 8 | data[offset + 1] as usize;
+}
+
+/// Create a patch from `source` to `target`. Bytes past the end of the
+/// source are always part of the patch, so that the patched file ends
+/// up with the same size. Panics if the target is too large for IPS or
+/// is smaller than the source, since the file can't be truncated in a
+/// way all patchers understand.
+pub fn create_patch(source: &[u8], target: &[u8]) -> Vec<u8> {
+    assert!(
+        target.len() <= MAX_OFFSET + 1,
+        "IPS patches can't address files larger than 16MB"
+    );
+    assert!(
+        target.len() >= source.len(),
+        "IPS patches can't shrink a file"
+    );
+
+    let is_changed = |offset: usize| {
+        return offset >= source.len() || source[offset] != target[offset];
+    };
+
+    let mut ips = SIGNATURE.to_vec();
+    let mut offset = 0;
+    while offset < target.len() {
+        if !is_changed(offset) {
+            offset += 1;
+            continue;
+        }
+
+        // Move the start back by a byte rather than starting a record
+        // at the offset that reads as EOF.
+        let start = if offset == END_OF_FILE_OFFSET {
+            offset - 1
+        }
+        else {
+            offset
+        };
+        let mut end = offset + 1;
+        while end < target.len()
+            && end - start < MAX_RECORD_SIZE_BYTES
+            && is_changed(end)
+        {
+            end += 1;
+        }
+
+        ips.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        ips.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        ips.extend_from_slice(&target[start..end]);
+        offset = end;
+    }
+
+    ips.extend_from_slice(END_OF_FILE);
+    return ips;
+}
+
+/// Apply a patch to `data`, which grows as needed. Panics if the patch
+/// is damaged.
+pub fn apply_patch(data: &mut Vec<u8>, ips: &[u8]) {
+    assert!(ips.starts_with(SIGNATURE), "Not an IPS patch");
+
+    let mut offset = SIGNATURE.len();
+    loop {
+        assert!(offset + 3 <= ips.len(), "The patch is damaged");
+        if &ips[offset..offset + 3] == END_OF_FILE {
+            offset += 3;
+            break;
+        }
+
+        let record_offset = read_u24_be(ips, offset);
+        let size = read_u16_be(ips, offset + 3);
+        offset += 5;
+
+        let (record_data, record_size) = if size == 0 {
+            let count = read_u16_be(ips, offset);
+            let byte = ips[offset + 2];
+            offset += 3;
+            (vec![byte; count], count)
+        }
+        else {
+            offset += size;
+            (ips[offset - size..offset].to_vec(), size)
+        };
+
+        let end = record_offset + record_size;
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[record_offset..end].copy_from_slice(&record_data);
+    }
+
+    if ips.len() == offset + 3 {
+        data.truncate(read_u24_be(ips, offset));
+    }
+}
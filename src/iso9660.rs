@@ -0,0 +1,488 @@
+//! Reader for the ISO-9660 filesystem inside the data track. We use it
+//! to find where each file lives in the image instead of relying on
+//! hardcoded offsets that only hold for one specific layout.
+//!
+//! Only the parts of the standard that the game disc actually uses are
+//! implemented:
+//! - the primary volume descriptor at sector 16
+//! - the little-endian (type L) path table, which lists every directory
+//! - the directory records of each directory, which list the files
+//!
+//! All multi-byte numbers we read are stored either in little-endian
+//! only or in "both-endian" form, where the little-endian copy comes
+//! first. We always read the little-endian copy.
+
+use std::io::{Read, Seek, Write};
+
+use crate::image::{self, RomImage};
+use crate::sector;
+
+const PRIMARY_VOLUME_DESCRIPTOR_SECTOR: usize = 16;
+const STANDARD_IDENTIFIER: &[u8] = b"CD001";
+
+const VOLUME_DESCRIPTOR_TERMINATOR_TYPE: u8 = 255;
+
+const PVD_TYPE_OFFSET: usize = 0;
+const PVD_IDENTIFIER_OFFSET: usize = 1;
+const PVD_VOLUME_SIZE_OFFSET: usize = 80;
+const PVD_PATH_TABLE_SIZE_OFFSET: usize = 132;
+const PVD_PATH_TABLE_LOCATION_OFFSET: usize = 140;
+const PVD_ROOT_RECORD_OFFSET: usize = 156;
+
+const RECORD_LENGTH_OFFSET: usize = 0;
+const RECORD_EXTENT_OFFSET: usize = 2;
+const RECORD_SIZE_OFFSET: usize = 10;
+const RECORD_FLAGS_OFFSET: usize = 25;
+const RECORD_NAME_LENGTH_OFFSET: usize = 32;
+const RECORD_NAME_OFFSET: usize = 33;
+
+const RECORD_FLAG_DIRECTORY: u8 = 0x02;
+
+// CD-ROM/XA extends each directory record with 14 bytes in the system
+// use area, right after the name. They contain a 2 byte big-endian
+// attribute field at offset 4 and the signature "XA" at offset 6.
+const XA_ATTRIBUTES_OFFSET: usize = 4;
+const XA_SIGNATURE_OFFSET: usize = 6;
+const XA_SIGNATURE: &[u8] = b"XA";
+const XA_RECORD_SIZE: usize = 14;
+
+const XA_ATTRIBUTE_FORM2: u16 = 0x1000;
+
+/// The kind of sectors a file is stored in.
+///
+/// Most files are plain data and use Form 1 sectors, which have 2048
+/// bytes of payload protected by EDC and ECC. Streamed audio and video
+/// (`master.xa`, `movie.str`) use Form 2 sectors, which trade the ECC
+/// for 2324 bytes of payload. Since the subheader of each sector is
+/// needed to interpret these files, we handle them as whole 2336 byte
+/// Mode 2 sectors, ie subheader, payload and EDC.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SectorForm {
+    Form1,
+    Form2,
+}
+
+/// A file or directory in the filesystem.
+pub struct FileEntry {
+    /// Full path from the root, in lowercase and without the `;1`
+    /// version suffix, eg `data/wa_mrg.mrg`. Directories don't have a
+    /// trailing slash and the root directory is the empty string.
+    pub path: String,
+
+    /// Index of the first sector of the file. Files are always stored
+    /// in contiguous sectors.
+    pub extent_sector: usize,
+
+    /// Size of the file in bytes.
+    pub size_bytes: usize,
+
+    pub is_directory: bool,
+
+    pub form: SectorForm,
+
+    /// Sector and byte offset within it of the directory record that
+    /// describes this file. Not set for directories.
+    record_location: Option<(usize, usize)>,
+}
+
+impl FileEntry {
+    /// Number of sectors occupied by the file. The last one might only
+    /// be partially used.
+    pub fn size_sectors(&self) -> usize {
+        return self.size_bytes.div_ceil(sector::DATA_SIZE_BYTES);
+    }
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> u16 {
+    return u16::from_le_bytes([data[offset], data[offset + 1]]);
+}
+
+/// Write a number in both-endian form: little-endian immediately
+/// followed by big-endian.
+fn write_u32_both(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    data[offset + 4..offset + 8].copy_from_slice(&value.to_be_bytes());
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> u32 {
+    return u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ]);
+}
+
+/// Convert a name as stored on the disc into the form we use for
+/// lookups: lowercase and without the version suffix. Some mastering
+/// tools also leave a trailing dot on files without an extension.
+fn normalize_name(raw_name: &[u8]) -> String {
+    let name = String::from_utf8_lossy(raw_name).to_lowercase();
+    let name = match name.split_once(';') {
+        Some((name, _version)) => name,
+        None => &name,
+    };
+    return name.trim_end_matches('.').to_string();
+}
+
+/// Read `size_bytes` bytes of data starting at the given sector. The
+/// data may span several sectors.
+fn read_extent<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+    sector_index: usize,
+    size_bytes: usize,
+) -> Vec<u8> {
+    let size_sectors = size_bytes.div_ceil(sector::DATA_SIZE_BYTES);
+    let mut data = Vec::with_capacity(size_sectors * sector::DATA_SIZE_BYTES);
+
+    for i in 0..size_sectors {
+        data.extend_from_slice(&rom.read_sector_data(sector_index + i));
+    }
+
+    data.truncate(size_bytes);
+    return data;
+}
+
+/// A single directory record, as found in a directory's extent or in
+/// the root record of the volume descriptor.
+struct DirectoryRecord {
+    extent_sector: usize,
+    size_bytes: usize,
+    is_directory: bool,
+    raw_name: Vec<u8>,
+    form: SectorForm,
+
+    /// Byte offset of the record from the start of its directory.
+    offset: usize,
+}
+
+impl DirectoryRecord {
+    fn parse(record: &[u8], offset: usize) -> DirectoryRecord {
+        let name_length = record[RECORD_NAME_LENGTH_OFFSET] as usize;
+        let name_end = RECORD_NAME_OFFSET + name_length;
+
+        // The name is padded so that the system use area starts at an
+        // even offset.
+        let xa_start = name_end + (name_end % 2);
+        let xa_record = record.get(xa_start..xa_start + XA_RECORD_SIZE);
+        let form = match xa_record {
+            Some(xa)
+                if &xa[XA_SIGNATURE_OFFSET..XA_SIGNATURE_OFFSET + 2]
+                    == XA_SIGNATURE =>
+            {
+                let attributes = u16::from_be_bytes([
+                    xa[XA_ATTRIBUTES_OFFSET],
+                    xa[XA_ATTRIBUTES_OFFSET + 1],
+                ]);
+                if attributes & XA_ATTRIBUTE_FORM2 != 0 {
+                    SectorForm::Form2
+                }
+                else {
+                    SectorForm::Form1
+                }
+            }
+            _ => SectorForm::Form1,
+        };
+
+        return DirectoryRecord {
+            extent_sector: read_u32_le(record, RECORD_EXTENT_OFFSET) as usize,
+            size_bytes: read_u32_le(record, RECORD_SIZE_OFFSET) as usize,
+            is_directory: record[RECORD_FLAGS_OFFSET] & RECORD_FLAG_DIRECTORY
+                != 0,
+            raw_name: record[RECORD_NAME_OFFSET..name_end].to_vec(),
+            form,
+            offset,
+        };
+    }
+
+    /// The first two records of every directory are the directory
+    /// itself and its parent. Their names are the single bytes 0 and
+    /// 1 respectively.
+    fn is_self_or_parent(&self) -> bool {
+        return self.raw_name == [0] || self.raw_name == [1];
+    }
+}
+
+/// Read every directory record in a directory's extent. Records never
+/// cross sector boundaries, so the unused space at the end of each
+/// sector is zero-filled. A zero length byte means we should skip to
+/// the next sector.
+fn read_directory_records<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+    extent_sector: usize,
+    size_bytes: usize,
+) -> Vec<DirectoryRecord> {
+    let directory_data = read_extent(rom, extent_sector, size_bytes);
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    while offset < directory_data.len() {
+        let record_length =
+            directory_data[offset + RECORD_LENGTH_OFFSET] as usize;
+
+        if record_length == 0 {
+            let next_sector = offset / sector::DATA_SIZE_BYTES + 1;
+            offset = next_sector * sector::DATA_SIZE_BYTES;
+            continue;
+        }
+
+        records.push(DirectoryRecord::parse(
+            &directory_data[offset..offset + record_length],
+            offset,
+        ));
+        offset += record_length;
+    }
+
+    return records;
+}
+
+/// A directory as listed in the path table.
+struct PathTableEntry {
+    extent_sector: usize,
+    path: String,
+}
+
+/// Read the path table and resolve the full path of every directory.
+/// The table lists directories in an order where a parent always comes
+/// before its children, so we can build the paths in a single pass.
+/// Parent numbers are 1-based indices into the table itself, and the
+/// root directory is its own parent.
+fn read_path_table<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+    pvd: &[u8],
+) -> Vec<PathTableEntry> {
+    let table_size = read_u32_le(pvd, PVD_PATH_TABLE_SIZE_OFFSET) as usize;
+    let table_sector =
+        read_u32_le(pvd, PVD_PATH_TABLE_LOCATION_OFFSET) as usize;
+    let table = read_extent(rom, table_sector, table_size);
+
+    let mut entries: Vec<PathTableEntry> = Vec::new();
+    let mut offset = 0;
+
+    while offset < table.len() {
+        let name_length = table[offset] as usize;
+        let extent_sector = read_u32_le(&table, offset + 2) as usize;
+        let parent_number = read_u16_le(&table, offset + 6) as usize;
+        let raw_name = &table[offset + 8..offset + 8 + name_length];
+
+        let path = if entries.is_empty() {
+            // The root directory has a single zero byte as its name.
+            String::new()
+        }
+        else {
+            let parent = &entries[parent_number - 1];
+            let name = normalize_name(raw_name);
+            if parent.path.is_empty() {
+                name
+            }
+            else {
+                parent.path.clone() + "/" + &name
+            }
+        };
+
+        entries.push(PathTableEntry {
+            extent_sector,
+            path,
+        });
+
+        // Names are padded to an even length.
+        offset += 8 + name_length + name_length % 2;
+    }
+
+    return entries;
+}
+
+/// Check that the data track contains an ISO-9660 filesystem, ie that
+/// it has a primary volume descriptor at sector 16.
+pub fn has_primary_volume_descriptor<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+) -> bool {
+    if rom.sector_count() <= PRIMARY_VOLUME_DESCRIPTOR_SECTOR {
+        return false;
+    }
+
+    let pvd = rom.read_sector_data(PRIMARY_VOLUME_DESCRIPTOR_SECTOR);
+    return pvd[PVD_TYPE_OFFSET] == 1
+        && &pvd[PVD_IDENTIFIER_OFFSET..PVD_IDENTIFIER_OFFSET + 5]
+            == STANDARD_IDENTIFIER;
+}
+
+/// Read the whole filesystem and return an entry for each directory and
+/// file in it, including the root directory.
+///
+/// Panics if the primary volume descriptor is missing, which means the
+/// given file either isn't a game image or isn't a format we support.
+pub fn read_filesystem<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+) -> Vec<FileEntry> {
+    assert!(
+        has_primary_volume_descriptor(rom),
+        "No ISO-9660 primary volume descriptor found"
+    );
+    let pvd = rom.read_sector_data(PRIMARY_VOLUME_DESCRIPTOR_SECTOR);
+
+    let root_record = DirectoryRecord::parse(&pvd[PVD_ROOT_RECORD_OFFSET..], 0);
+    let mut entries = Vec::new();
+
+    for directory in read_path_table(rom, &pvd) {
+        // The path table doesn't store the size of each directory's
+        // extent. It is only found in the directory's own "self" record
+        // or, for the root, in the volume descriptor.
+        let size_bytes = if directory.path.is_empty() {
+            root_record.size_bytes
+        }
+        else {
+            let first_sector = rom.read_sector_data(directory.extent_sector);
+            DirectoryRecord::parse(&first_sector, 0).size_bytes
+        };
+
+        let records =
+            read_directory_records(rom, directory.extent_sector, size_bytes);
+
+        for record in records {
+            // Subdirectories are listed through the path table, so we
+            // only need the files here.
+            if record.is_self_or_parent() || record.is_directory {
+                continue;
+            }
+
+            let name = normalize_name(&record.raw_name);
+            let path = if directory.path.is_empty() {
+                name
+            }
+            else {
+                directory.path.clone() + "/" + &name
+            };
+
+            entries.push(FileEntry {
+                path,
+                extent_sector: record.extent_sector,
+                size_bytes: record.size_bytes,
+                is_directory: false,
+                form: record.form,
+                record_location: Some((
+                    directory.extent_sector
+                        + record.offset / sector::DATA_SIZE_BYTES,
+                    record.offset % sector::DATA_SIZE_BYTES,
+                )),
+            });
+        }
+
+        entries.push(FileEntry {
+            path: directory.path,
+            extent_sector: directory.extent_sector,
+            size_bytes,
+            is_directory: true,
+            form: SectorForm::Form1,
+            record_location: None,
+        });
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    return entries;
+}
+
+/// Find a single file by its path. The lookup is case-insensitive and
+/// the version suffix is optional, so `SLUS_014.11;1` and `slus_014.11`
+/// are equivalent.
+pub fn find_file<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+    path: &str,
+) -> Option<FileEntry> {
+    let path = path
+        .split('/')
+        .map(|component| normalize_name(component.as_bytes()))
+        .collect::<Vec<String>>()
+        .join("/");
+
+    return read_filesystem(rom)
+        .into_iter()
+        .find(|entry| entry.path == path);
+}
+
+/// Rewrite the directory record of a file with a new location and
+/// size. Directories can't be moved.
+pub fn write_file_record<F: Read + Write + Seek>(
+    rom: &mut RomImage<F>,
+    entry: &FileEntry,
+    extent_sector: usize,
+    size_bytes: usize,
+) {
+    let (sector, offset) = entry
+        .record_location
+        .expect("Only the records of files can be rewritten");
+
+    let mut sector_data = rom.read_sector_data(sector);
+    write_u32_both(
+        &mut sector_data,
+        offset + RECORD_EXTENT_OFFSET,
+        extent_sector as u32,
+    );
+    write_u32_both(
+        &mut sector_data,
+        offset + RECORD_SIZE_OFFSET,
+        size_bytes as u32,
+    );
+    rom.write_sector_data(sector, &sector_data);
+}
+
+/// Find the sector of the volume descriptor set terminator, which
+/// comes after the primary volume descriptor and any others.
+pub fn read_volume_descriptor_terminator<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+) -> usize {
+    let mut sector = PRIMARY_VOLUME_DESCRIPTOR_SECTOR;
+    while rom.read_sector_data(sector)[PVD_TYPE_OFFSET]
+        != VOLUME_DESCRIPTOR_TERMINATOR_TYPE
+    {
+        sector += 1;
+    }
+    return sector;
+}
+
+/// Read the size of the volume in sectors, as stored in the primary
+/// volume descriptor.
+pub fn read_volume_size<F: Read + Seek>(rom: &mut RomImage<F>) -> usize {
+    let pvd = rom.read_sector_data(PRIMARY_VOLUME_DESCRIPTOR_SECTOR);
+    return read_u32_le(&pvd, PVD_VOLUME_SIZE_OFFSET) as usize;
+}
+
+/// Update the size of the volume in sectors in the primary volume
+/// descriptor.
+pub fn write_volume_size<F: Read + Write + Seek>(
+    rom: &mut RomImage<F>,
+    size_sectors: usize,
+) {
+    let mut pvd = rom.read_sector_data(PRIMARY_VOLUME_DESCRIPTOR_SECTOR);
+    write_u32_both(&mut pvd, PVD_VOLUME_SIZE_OFFSET, size_sectors as u32);
+    rom.write_sector_data(PRIMARY_VOLUME_DESCRIPTOR_SECTOR, &pvd);
+}
+
+/// Extract every file in the filesystem into `output_dir`, recreating
+/// the directory tree. Form 1 files are written as their plain data.
+/// Form 2 files are written as a sequence of 2336 byte Mode 2 sectors,
+/// which is the format most tools for XA audio and STR video expect.
+/// Files are written one sector at a time, so even the large streams
+/// are never held in memory.
+pub fn extract_all_files<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+    output_dir: &std::path::Path,
+) {
+    for entry in read_filesystem(rom) {
+        let output_path = output_dir.join(&entry.path);
+
+        if entry.is_directory {
+            std::fs::create_dir_all(&output_path).unwrap();
+            continue;
+        }
+
+        let file = std::fs::File::create(&output_path).unwrap();
+        let mut writer = std::io::BufWriter::new(file);
+        for index in 0..entry.size_sectors() {
+            writer
+                .write_all(&image::read_file_sector(rom, &entry, index))
+                .unwrap();
+        }
+        writer.flush().unwrap();
+    }
+}
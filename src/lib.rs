@@ -1,4 +1,22 @@
+pub mod bps;
+pub mod card;
+pub mod cue;
 pub mod duelist;
+pub mod equip;
+pub mod fusion;
+pub mod identify;
 pub mod image;
+pub mod ips;
+pub mod iso9660;
+pub mod name;
+pub mod overlay;
+pub mod plan;
+pub mod ppf;
+pub mod rebuild;
+pub mod ritual;
+pub mod sector;
 pub mod testing;
 pub mod text;
+pub mod text_pool;
+pub mod vcdiff;
+pub mod verify;
@@ -1,12 +1,12 @@
-use std::fs;
-use std::io::Write;
+use std::io::{Read, Seek, Write};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 use fmde::*;
 
 /// CLI utility to mod a .bin/.cue image of the PSX game Yu-Gi-Oh!
-/// Forbidden Memories. The ROM must be the US version: SLUS-01411.
+/// Forbidden Memories. The ROM must be the US version: SLUS-01411. This
+/// is checked before any game data is read or written.
 #[derive(Parser)]
 #[command(version)]
 struct Args {
@@ -26,6 +26,52 @@ enum Command {
         dump_dir: std::path::PathBuf,
     },
 
+    /// Compare a modified ROM file with the original and dump only the
+    /// card lists that differ, as a mod that `apply` accepts.
+    Diff {
+        /// Path of the original ROM file.
+        base_rom_path: std::path::PathBuf,
+
+        /// Path of the modified ROM file.
+        modded_rom_path: std::path::PathBuf,
+
+        /// Directory in which to dump the mod. If it doesn't exist it
+        /// will be created.
+        output_dir: std::path::PathBuf,
+    },
+
+    /// Identify the release of the game in a ROM file and print the
+    /// CRC-32 of the image and of the game files.
+    Identify {
+        /// Path of the ROM file.
+        rom_path: std::path::PathBuf,
+    },
+
+    /// Extract every file in the ROM's filesystem into a directory.
+    Extract {
+        /// Path of the ROM file.
+        rom_path: std::path::PathBuf,
+
+        /// Directory in which to extract the files. If it doesn't exist
+        /// it will be created.
+        output_dir: std::path::PathBuf,
+    },
+
+    /// Rebuild a ROM file with the files of an extracted tree. Files
+    /// are allowed to change size. A .cue file is written next to the
+    /// output.
+    Rebuild {
+        /// Path of the ROM file to use as a template.
+        rom_path: std::path::PathBuf,
+
+        /// Directory with the files, as written by `extract`. Missing
+        /// files are taken from the template.
+        files_dir: std::path::PathBuf,
+
+        /// Path to save the output.
+        output_path: std::path::PathBuf,
+    },
+
     /// Apply a mod to a ROM file. The mod is defined as a set of .csv
     /// files that define each duelists deck and droplists.
     Apply {
@@ -36,6 +82,62 @@ enum Command {
 
         /// Path to save the output.
         output_path: std::path::PathBuf,
+
+        /// Write a patch with the changes to the ROM file in the given
+        /// format, instead of a whole modified ROM.
+        #[arg(long, value_enum)]
+        patch: Option<PatchFormat>,
+
+        /// Include undo data in a PPF patch, so that it can be
+        /// reverted.
+        #[arg(long)]
+        undo: bool,
+
+        /// Description of a PPF patch, up to 50 characters.
+        #[arg(long, default_value = "")]
+        description: String,
+
+        /// Text file to embed in a PPF patch as its file ID, usually a
+        /// file_id.diz.
+        #[arg(long)]
+        file_id: Option<std::path::PathBuf>,
+    },
+
+    /// Show what `apply` would change, without writing anything. Every
+    /// card added to, removed from or reweighted in a card list is
+    /// listed with its old and new weight.
+    Plan {
+        /// Path of the ROM file.
+        rom_path: std::path::PathBuf,
+
+        csv_path: std::path::PathBuf,
+    },
+
+    /// Work with patches.
+    Patch {
+        #[command(subcommand)]
+        command: PatchCommand,
+    },
+
+    /// Convert a ROM file to a different sector layout.
+    Convert {
+        /// Path of the ROM file.
+        rom_path: std::path::PathBuf,
+
+        /// Path to save the output.
+        output_path: std::path::PathBuf,
+
+        /// Sector layout of the output.
+        #[arg(long, value_enum)]
+        format: OutputFormat,
+    },
+
+    /// Check every sector of a ROM file for errors and report the ones
+    /// that are damaged, along with the file they belong to. Iso images
+    /// can't be checked.
+    Verify {
+        /// Path of the ROM file.
+        rom_path: std::path::PathBuf,
     },
 
     /// Extract the data from the ROM and rewrite them in. Useful only
@@ -49,45 +151,569 @@ enum Command {
     },
 }
 
+#[derive(Subcommand)]
+enum PatchCommand {
+    /// Apply a patch to a ROM file. PPF 1.0, 2.0 and 3.0, BPS, VCDIFF
+    /// (xdelta) and IPS patches are supported, and the format is
+    /// detected from the patch. If the ROM is given as a .cue file, a
+    /// copy of it that points to the output is written next to the
+    /// output.
+    Apply {
+        /// Path of the ROM file.
+        rom_path: std::path::PathBuf,
+
+        /// Path of the patch.
+        patch_path: std::path::PathBuf,
+
+        /// Path to save the output.
+        output_path: std::path::PathBuf,
+
+        /// Revert a PPF patch instead, using its undo data.
+        #[arg(long)]
+        undo: bool,
+    },
+}
+
+/// Patch formats that `apply` can write.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PatchFormat {
+    /// PPF 3.0, the usual format for PS1 patches.
+    Ppf,
+
+    /// BPS, with checksums of the original and patched ROM.
+    Bps,
+
+    /// VCDIFF, the format of xdelta.
+    Vcdiff,
+
+    /// IPS. It can only address 16MB, so the patch applies to
+    /// SLUS_014.11 instead of the whole ROM, and it can only hold
+    /// changes to that file.
+    Ips,
+}
+
+/// Sector layouts that we can write, see `image::SectorFormat`.
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// Raw 2352 byte sectors in a .bin file, with a .cue file.
+    Bin,
+
+    /// 2336 byte Mode 2 sectors in a .bin file, with a .cue file.
+    Mode2,
+
+    /// 2048 byte sectors with only the data. Streamed audio and video
+    /// can't be stored in this layout.
+    Iso,
+}
+
+impl From<OutputFormat> for image::SectorFormat {
+    fn from(format: OutputFormat) -> image::SectorFormat {
+        return match format {
+            OutputFormat::Bin => image::SectorFormat::Raw,
+            OutputFormat::Mode2 => image::SectorFormat::Mode2,
+            OutputFormat::Iso => image::SectorFormat::Iso,
+        };
+    }
+}
+
 fn dump_data(rom_path: &std::path::Path, dump_dir: &std::path::Path) {
-    // TODO: this loads the entire file in memory. Try accessing it
-    // directly from the disc.
-    let rom_file = fs::read(rom_path).expect("Failed to load file");
+    let mut rom = image::RomImage::open(rom_path);
+    identify::check_supported_release(&mut rom);
 
-    let slus = image::read_slus_from_bin(&rom_file);
-    let wa_mrg = image::read_wa_mrg_from_bin(&rom_file);
+    let slus = image::read_slus_from_bin(&mut rom);
+    let wa_mrg = image::read_wa_mrg_from_bin(&mut rom);
 
     let duelist_info = duelist::read_all_duelists(&slus, &wa_mrg);
     let card_names = duelist::get_card_names(&slus);
 
-    duelist::dump_all_duelists_csv(&dump_dir, &duelist_info, &card_names);
+    duelist::dump_all_duelists_csv(dump_dir, &duelist_info, &card_names);
+
+    let cards = card::read_all_cards(&slus, &wa_mrg);
+    card::dump_all_cards_csv(&dump_dir.join("cards.csv"), &cards, &card_names);
+    card::dump_descriptions_csv(
+        &dump_dir.join("descriptions.csv"),
+        &cards,
+        &card_names,
+    );
+
+    let names = name::read_all_names(&slus);
+    name::dump_names_csv(&dump_dir.join("names.csv"), &names);
+
+    let equip_table = equip::read_equip_table(&wa_mrg);
+    equip::dump_equips_csv(
+        &dump_dir.join("equips.csv"),
+        &equip_table,
+        &card_names,
+    );
+
+    let fusion_table = fusion::read_fusion_table(&wa_mrg);
+    fusion::dump_fusions_csv(
+        &dump_dir.join("fusions.csv"),
+        &fusion_table,
+        &card_names,
+    );
 }
 
-fn apply(
+fn diff(
+    base_rom_path: &std::path::Path,
+    modded_rom_path: &std::path::Path,
+    output_dir: &std::path::Path,
+) {
+    let mut base_rom = image::RomImage::open(base_rom_path);
+    identify::check_supported_release(&mut base_rom);
+    let mut modded_rom = image::RomImage::open(modded_rom_path);
+    identify::check_supported_release(&mut modded_rom);
+
+    let base_slus = image::read_slus_from_bin(&mut base_rom);
+    let base_wa_mrg = image::read_wa_mrg_from_bin(&mut base_rom);
+    let base_duelists = duelist::read_all_duelists(&base_slus, &base_wa_mrg);
+
+    let modded_slus = image::read_slus_from_bin(&mut modded_rom);
+    let modded_wa_mrg = image::read_wa_mrg_from_bin(&mut modded_rom);
+    let modded_duelists =
+        duelist::read_all_duelists(&modded_slus, &modded_wa_mrg);
+
+    let card_names = duelist::get_card_names(&base_slus);
+    let written_paths = duelist::dump_changed_duelists_csv(
+        output_dir,
+        &base_duelists,
+        &modded_duelists,
+        &card_names,
+    );
+
+    for path in &written_paths {
+        println!("{}", path.display());
+    }
+    println!("{} card lists differ", written_paths.len());
+}
+
+fn identify(rom_path: &std::path::Path) {
+    let mut rom = image::RomImage::open(rom_path);
+    let identification = identify::identify_image(&mut rom);
+
+    match (&identification.executable, identification.release) {
+        (Some(_), Some(release)) => println!(
+            "Release: {} ({} {}, {})",
+            release.title, release.region, release.language, release.executable
+        ),
+        (Some(executable), None) => {
+            println!("Release: unknown, boots {executable}")
+        }
+        (None, _) => println!("Release: unknown, no system.cnf"),
+    }
+
+    println!("Image CRC-32:   {:08x}", identification.image_crc);
+    if let Some(slus_crc) = identification.slus_crc {
+        println!("SLUS CRC-32:    {slus_crc:08x}");
+    }
+    if let Some(wa_mrg_crc) = identification.wa_mrg_crc {
+        println!("WA_MRG CRC-32:  {wa_mrg_crc:08x}");
+    }
+}
+
+fn extract(rom_path: &std::path::Path, output_dir: &std::path::Path) {
+    let mut rom = image::RomImage::open(rom_path);
+
+    iso9660::extract_all_files(&mut rom, output_dir);
+}
+
+fn rebuild(
     rom_path: &std::path::Path,
-    csv_dir: &std::path::Path,
+    files_dir: &std::path::Path,
     output_path: &std::path::Path,
 ) {
-    let mut rom_file = fs::read(rom_path).expect("Failed to load file");
-    let slus = image::read_slus_from_bin(&rom_file);
-    let mut wa_mrg = image::read_wa_mrg_from_bin(&rom_file);
+    let mut rom = image::RomImage::open(rom_path);
+    let changed_files = rebuild::load_changed_files(&mut rom, files_dir);
+
+    let mut output = image::copy_rom(rom_path, output_path, None);
+    rebuild::rebuild_image(&mut output, &changed_files);
+}
+
+/// Load a mod from .csv files and apply it to the game files of the
+/// ROM, in memory. Every table is laid out here, so a mod that doesn't
+/// fit fails before anything is written to the output.
+fn load_mod<F: Read + Write + Seek>(
+    rom: &mut image::RomImage<F>,
+    csv_dir: &std::path::Path,
+) -> Result<(image::SlusExecutable, image::WaMrgArchive), String> {
+    let mut slus = image::read_slus_from_bin(rom);
+    let mut wa_mrg = image::read_wa_mrg_from_bin(rom);
+
+    // descriptions.csv isn't applied, see the card module.
+    let mut cards = card::read_all_cards(&slus, &wa_mrg);
+    card::load_all_cards_csv(&csv_dir.join("cards.csv"), &mut cards);
+    card::write_all_cards(&mut slus, &mut wa_mrg, &cards);
+
+    let mut names = name::read_all_names(&slus);
+    name::load_names_csv(&csv_dir.join("names.csv"), &mut names);
+    let free_name_bytes = name::write_all_names(&mut slus, &names)
+        .map_err(|error| error.to_string())?;
+    println!("{free_name_bytes} bytes free in the name pool");
 
     let mut duelist_info = duelist::read_all_duelists(&slus, &wa_mrg);
     duelist::load_all_duelists_csv(csv_dir, &mut duelist_info);
     duelist::write_all_duelists(&mut wa_mrg, &duelist_info);
 
-    image::write_wa_mrg_to_bin(&mut rom_file, &wa_mrg);
+    let mut equip_table = equip::read_equip_table(&wa_mrg);
+    equip::load_equips_csv(&csv_dir.join("equips.csv"), &mut equip_table);
+    equip::write_equip_table(&mut wa_mrg, &equip_table)
+        .map_err(|error| error.to_string())?;
+
+    let mut fusion_table = fusion::read_fusion_table(&wa_mrg);
+    fusion::load_fusions_csv(&csv_dir.join("fusions.csv"), &mut fusion_table);
+    fusion::write_fusion_table(&mut wa_mrg, &fusion_table)
+        .map_err(|error| error.to_string())?;
+
+    return Ok((slus, wa_mrg));
+}
 
-    let mut output_file = fs::File::create_new(output_path).unwrap();
-    output_file.write_all(&rom_file).unwrap();
+/// Print an error for a mod that can't be applied and exit.
+fn exit_with_error(error: &str) -> ! {
+    eprintln!("Error: {error}");
+    std::process::exit(1);
+}
+
+fn apply(
+    rom_path: &std::path::Path,
+    csv_dir: &std::path::Path,
+    output_path: &std::path::Path,
+) {
+    let mut rom = image::RomImage::open(rom_path);
+    identify::check_supported_release(&mut rom);
+    let (slus, wa_mrg) = load_mod(&mut rom, csv_dir)
+        .unwrap_or_else(|error| exit_with_error(&error));
+
+    let mut output = image::copy_rom(rom_path, output_path, None);
+    image::write_slus_to_bin(&mut output, &slus);
+    image::write_wa_mrg_to_bin(&mut output, &wa_mrg);
+}
+
+fn plan(rom_path: &std::path::Path, csv_dir: &std::path::Path) {
+    let mut rom = image::RomImage::open(rom_path);
+    identify::check_supported_release(&mut rom);
+
+    let slus = image::read_slus_from_bin(&mut rom);
+    let wa_mrg = image::read_wa_mrg_from_bin(&mut rom);
+
+    let base_duelists = duelist::read_all_duelists(&slus, &wa_mrg);
+    let mut duelists = duelist::read_all_duelists(&slus, &wa_mrg);
+    duelist::load_all_duelists_csv(csv_dir, &mut duelists);
+    let card_names = duelist::get_card_names(&slus);
+    let card = |card_id: usize| {
+        return format!("{} {}", card_id + 1, card_names[card_id]);
+    };
+
+    let card_list_changes = plan::compare_duelists(&base_duelists, &duelists);
+    for card_list_change in &card_list_changes {
+        let duelist_id = card_list_change.duelist_id;
+        println!(
+            "{}.{}/{}",
+            duelist_id + 1,
+            duelists[duelist_id].name,
+            card_list_change.file_name
+        );
+
+        for change in &card_list_change.changes {
+            let card = card(change.card_id);
+            let old_percentage = plan::rate_percentage(change.old_rate);
+            let new_percentage = plan::rate_percentage(change.new_rate);
+
+            if change.old_rate == 0 {
+                println!(
+                    "  added      {card}: {} ({new_percentage:.2}%)",
+                    change.new_rate
+                );
+            }
+            else if change.new_rate == 0 {
+                println!(
+                    "  removed    {card}: was {} ({old_percentage:.2}%)",
+                    change.old_rate
+                );
+            }
+            else {
+                println!(
+                    "  reweighted {card}: {} ({old_percentage:.2}%) -> {} \
+                     ({new_percentage:.2}%)",
+                    change.old_rate, change.new_rate
+                );
+            }
+        }
+    }
+
+    // The other tables are compared card by card. descriptions.csv
+    // isn't applied, see the card module.
+    let base_cards = card::read_all_cards(&slus, &wa_mrg);
+    let mut cards = base_cards.clone();
+    card::load_all_cards_csv(&csv_dir.join("cards.csv"), &mut cards);
+
+    let base_names = name::read_all_names(&slus);
+    let mut names = name::read_all_names(&slus);
+    name::load_names_csv(&csv_dir.join("names.csv"), &mut names);
+
+    let base_equip_table = equip::read_equip_table(&wa_mrg);
+    let mut equip_table = equip::read_equip_table(&wa_mrg);
+    equip::load_equips_csv(&csv_dir.join("equips.csv"), &mut equip_table);
+
+    let base_fusion_table = fusion::read_fusion_table(&wa_mrg);
+    let mut fusion_table = fusion::read_fusion_table(&wa_mrg);
+    fusion::load_fusions_csv(&csv_dir.join("fusions.csv"), &mut fusion_table);
+
+    let changed_tables = [
+        (
+            "cards.csv",
+            "stats",
+            plan::compare_entries(&base_cards, &cards),
+        ),
+        (
+            "equips.csv",
+            "equips",
+            plan::compare_entries(
+                &base_equip_table.monsters,
+                &equip_table.monsters,
+            ),
+        ),
+        (
+            "fusions.csv",
+            "fusions",
+            plan::compare_entries(
+                &base_fusion_table.fusions,
+                &fusion_table.fusions,
+            ),
+        ),
+    ];
+    let renames = [
+        (
+            "card",
+            &base_names.card_names,
+            &names.card_names,
+            plan::compare_entries(&base_names.card_names, &names.card_names),
+        ),
+        (
+            "duelist",
+            &base_names.duelist_names,
+            &names.duelist_names,
+            plan::compare_entries(
+                &base_names.duelist_names,
+                &names.duelist_names,
+            ),
+        ),
+    ];
+
+    for (file_name, what, card_ids) in &changed_tables {
+        if card_ids.is_empty() {
+            continue;
+        }
+
+        println!("{file_name}");
+        for &card_id in card_ids {
+            println!("  changed the {what} of {}", card(card_id));
+        }
+    }
+    if renames.iter().any(|(_, _, _, ids)| !ids.is_empty()) {
+        println!("names.csv");
+    }
+    for (kind, base_names, names, ids) in &renames {
+        for &id in ids {
+            println!(
+                "  renamed {kind} {} {} to {}",
+                id + 1,
+                base_names[id],
+                names[id]
+            );
+        }
+    }
+
+    let changes_anything = !card_list_changes.is_empty()
+        || changed_tables.iter().any(|(_, _, ids)| !ids.is_empty())
+        || renames.iter().any(|(_, _, _, ids)| !ids.is_empty());
+    if !changes_anything {
+        println!("The mod doesn't change anything");
+    }
+}
+
+fn apply_as_patch(
+    rom_path: &std::path::Path,
+    csv_dir: &std::path::Path,
+    output_path: &std::path::Path,
+    format: PatchFormat,
+    undo: bool,
+    description: &str,
+    file_id_path: Option<&std::path::Path>,
+) {
+    assert!(
+        format == PatchFormat::Ppf
+            || (!undo && description.is_empty() && file_id_path.is_none()),
+        "Undo data, descriptions and file IDs are only supported for PPF \
+         patches"
+    );
+
+    let mut rom = image::RomImage::open(rom_path);
+    identify::check_supported_release(&mut rom);
+    let original_slus = image::read_slus_from_bin(&mut rom);
+    let original_wa_mrg = image::read_wa_mrg_from_bin(&mut rom);
+
+    let (slus, wa_mrg) = load_mod(&mut rom, csv_dir)
+        .unwrap_or_else(|error| exit_with_error(&error));
+
+    let mut rom = rom.overlay();
+    image::write_slus_to_bin(&mut rom, &slus);
+    image::write_wa_mrg_to_bin(&mut rom, &wa_mrg);
+
+    let patch = match format {
+        PatchFormat::Ppf => {
+            let file_id = file_id_path.map(|path| {
+                std::fs::read_to_string(path).expect("Failed to load file")
+            });
+            let patch = ppf::create_patch(
+                &mut rom.into_file(),
+                description,
+                undo,
+                file_id.as_deref(),
+            );
+            ppf::encode_patch(&patch)
+        }
+        PatchFormat::Bps => bps::create_patch(&mut rom.into_file()),
+        PatchFormat::Vcdiff => vcdiff::create_patch(&mut rom.into_file()),
+        PatchFormat::Ips => {
+            assert!(
+                wa_mrg.as_bytes() == original_wa_mrg.as_bytes(),
+                "The mod changes WA_MRG.MRG, but IPS patches can only hold \
+                 changes to SLUS_014.11"
+            );
+
+            ips::create_patch(original_slus.as_bytes(), slus.as_bytes())
+        }
+    };
+
+    let mut patch_file = std::fs::File::create_new(output_path).unwrap();
+    patch_file.write_all(&patch).unwrap();
+}
+
+/// Apply an IPS patch to SLUS_014.11, see `PatchFormat::Ips`.
+fn patch_apply_ips(
+    rom_path: &std::path::Path,
+    ips_patch: &[u8],
+    output_path: &std::path::Path,
+) {
+    identify::check_supported_release(&mut image::RomImage::open(rom_path));
+
+    let mut rom = image::copy_rom(rom_path, output_path, None);
+    let mut slus = image::read_slus_from_bin(&mut rom).as_bytes().to_vec();
+    ips::apply_patch(&mut slus, ips_patch);
+    image::write_slus_to_bin(&mut rom, &image::SlusExecutable::new(slus));
+}
+
+fn patch_apply(
+    rom_path: &std::path::Path,
+    patch_path: &std::path::Path,
+    output_path: &std::path::Path,
+    undo: bool,
+) {
+    let patch = std::fs::read(patch_path).expect("Failed to load file");
+    let is_ppf = patch.starts_with(b"PPF");
+    assert!(is_ppf || !undo, "Only PPF patches can be reverted");
+
+    if patch.starts_with(ips::SIGNATURE) {
+        patch_apply_ips(rom_path, &patch, output_path);
+        return;
+    }
+
+    // The other formats are made for the image file, not just the data
+    // track, so we patch the whole file.
+    let (bin_path, cue_sheet) = if image::is_cue_path(rom_path) {
+        let cue_sheet = cue::load_cue_sheet(rom_path);
+        assert!(
+            cue_sheet.files.len() == 1,
+            "Patches can only be applied to ROMs stored in a single file"
+        );
+        let bin_path =
+            rom_path.parent().unwrap().join(&cue_sheet.files[0].path);
+        (bin_path, Some(cue_sheet))
+    }
+    else {
+        (rom_path.to_path_buf(), None)
+    };
+
+    let output_bin_path = match cue_sheet {
+        Some(_) if image::is_cue_path(output_path) => {
+            output_path.with_extension("bin")
+        }
+        _ => output_path.to_path_buf(),
+    };
+
+    let mut input =
+        std::fs::File::open(&bin_path).expect("Failed to load file");
+    let mut output = std::fs::File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&output_bin_path)
+        .unwrap();
+
+    if is_ppf {
+        std::io::copy(&mut input, &mut output).unwrap();
+        ppf::apply_patch(&mut output, &ppf::parse_patch(&patch), undo);
+    }
+    else if patch.starts_with(bps::SIGNATURE) {
+        bps::apply_patch(&mut input, &mut output, &patch);
+    }
+    else if patch.starts_with(vcdiff::SIGNATURE) {
+        vcdiff::apply_patch(&mut input, &mut output, &patch);
+    }
+    else {
+        panic!("Unrecognized patch format");
+    }
+
+    if let Some(mut cue_sheet) = cue_sheet {
+        let bin_name = output_bin_path.file_name().unwrap().to_string_lossy();
+        cue_sheet.files[0].path = bin_name.to_string();
+        cue::dump_cue_sheet(&output_bin_path.with_extension("cue"), &cue_sheet);
+    }
+}
+
+fn convert(
+    rom_path: &std::path::Path,
+    output_path: &std::path::Path,
+    format: OutputFormat,
+) {
+    image::copy_rom(rom_path, output_path, Some(format.into()));
+}
+
+fn verify(rom_path: &std::path::Path) {
+    let mut rom = image::RomImage::open(rom_path);
+    let bad_sectors = verify::verify_image(&mut rom);
+
+    for bad_sector in &bad_sectors {
+        let errors = bad_sector
+            .errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        let location = match &bad_sector.path {
+            Some(path) if path.is_empty() => "root directory".to_string(),
+            Some(path) => path.clone(),
+            None => "outside any file".to_string(),
+        };
+        println!("Sector {} ({location}): {errors}", bad_sector.sector_index);
+    }
+
+    println!(
+        "{} sectors checked, {} bad",
+        rom.sector_count(),
+        bad_sectors.len()
+    );
+    if !bad_sectors.is_empty() {
+        std::process::exit(1);
+    }
 }
 
 fn passthrough_test(rom_path: &std::path::Path, output_path: &std::path::Path) {
-    let mut rom_file = fs::read(rom_path).expect("Failed to load file");
+    identify::check_supported_release(&mut image::RomImage::open(rom_path));
+
+    let mut rom = image::copy_rom(rom_path, output_path, None);
 
-    testing::passthrough_test(&mut rom_file);
-    let mut output_file = fs::File::create_new(output_path).unwrap();
-    output_file.write_all(&rom_file).unwrap();
+    testing::passthrough_test(&mut rom);
 }
 
 fn main() {
@@ -97,12 +723,72 @@ fn main() {
         Command::Dump { rom_path, dump_dir } => {
             dump_data(&rom_path, &dump_dir);
         }
+        Command::Diff {
+            base_rom_path,
+            modded_rom_path,
+            output_dir,
+        } => {
+            diff(&base_rom_path, &modded_rom_path, &output_dir);
+        }
+        Command::Identify { rom_path } => {
+            identify(&rom_path);
+        }
+        Command::Extract {
+            rom_path,
+            output_dir,
+        } => {
+            extract(&rom_path, &output_dir);
+        }
+        Command::Rebuild {
+            rom_path,
+            files_dir,
+            output_path,
+        } => {
+            rebuild(&rom_path, &files_dir, &output_path);
+        }
         Command::Apply {
             rom_path,
             csv_path,
             output_path,
+            patch,
+            undo,
+            description,
+            file_id,
+        } => match patch {
+            Some(format) => apply_as_patch(
+                &rom_path,
+                &csv_path,
+                &output_path,
+                format,
+                undo,
+                &description,
+                file_id.as_deref(),
+            ),
+            None => apply(&rom_path, &csv_path, &output_path),
+        },
+        Command::Plan { rom_path, csv_path } => {
+            plan(&rom_path, &csv_path);
+        }
+        Command::Patch {
+            command:
+                PatchCommand::Apply {
+                    rom_path,
+                    patch_path,
+                    output_path,
+                    undo,
+                },
+        } => {
+            patch_apply(&rom_path, &patch_path, &output_path, undo);
+        }
+        Command::Convert {
+            rom_path,
+            output_path,
+            format,
         } => {
-            apply(&rom_path, &csv_path, &output_path);
+            convert(&rom_path, &output_path, format);
+        }
+        Command::Verify { rom_path } => {
+            verify(&rom_path);
         }
         Command::Passthrough {
             rom_path,
@@ -0,0 +1,212 @@
+//! Module to manipulate the names of cards and duelists. The function
+//! names follow the same convention as the duelist module:
+//! - read/write if they operate on the ROM file
+//! - load/dump if they operate on csv files
+//!
+//! The names are strings in the slus file, in a pool of text that
+//! starts at `NAME_OFFSET`. Each card and each duelist has a 2 byte
+//! little-endian pointer to its name, relative to `NAME_OFFSET`, in the
+//! tables at `CARD_NAME_INDICES_OFFSET` and
+//! `DUELIST_NAME_INDICES_OFFSET`. The pointers between the two tables,
+//! at `OTHER_TEXT_INDICES_OFFSET`, point into the same pool. We don't
+//! know what their strings are for, but they are kept as they are when
+//! the names are laid out again.
+
+use csv::{ReaderBuilder, Writer};
+
+use crate::duelist::{
+    CARD_NAME_INDICES_OFFSET, DUELIST_NAME_INDICES_OFFSET, NAME_OFFSET,
+    NUMBER_OF_CARDS, NUMBER_OF_DUELISTS,
+};
+use crate::image::SlusExecutable;
+use crate::text;
+use crate::text_pool::{LayoutError, PointerTable, TextPool};
+
+/// The pointers that fill the gap from the end of the card name table
+/// to the start of the duelist name table.
+pub const OTHER_TEXT_INDICES_OFFSET: usize =
+    CARD_NAME_INDICES_OFFSET + 2 * NUMBER_OF_CARDS;
+pub const NUMBER_OF_OTHER_TEXTS: usize =
+    (DUELIST_NAME_INDICES_OFFSET - OTHER_TEXT_INDICES_OFFSET) / 2;
+
+/// The index of each pointer table in `NAME_POOL`.
+const CARD_NAMES_TABLE: usize = 0;
+const DUELIST_NAMES_TABLE: usize = 2;
+
+/// The pool of names, with every table that points into it.
+pub const NAME_POOL: TextPool = TextPool {
+    name: "names",
+    base_offset: NAME_OFFSET,
+    pointer_tables: &[
+        PointerTable {
+            offset: CARD_NAME_INDICES_OFFSET,
+            count: NUMBER_OF_CARDS,
+        },
+        PointerTable {
+            offset: OTHER_TEXT_INDICES_OFFSET,
+            count: NUMBER_OF_OTHER_TEXTS,
+        },
+        PointerTable {
+            offset: DUELIST_NAME_INDICES_OFFSET,
+            count: NUMBER_OF_DUELISTS,
+        },
+    ],
+    unknown_pointers: &[],
+};
+
+/// The names of every card and every duelist, by id.
+#[derive(PartialEq, Eq)]
+pub struct Names {
+    pub card_names: Vec<String>,
+    pub duelist_names: Vec<String>,
+}
+
+/// Read the names of all the cards and duelists from the given slus
+/// file.
+pub fn read_all_names(slus: &SlusExecutable) -> Names {
+    let strings = NAME_POOL.read_strings(slus.as_bytes());
+    let decode = |strings: &[Vec<u8>]| {
+        return strings
+            .iter()
+            .map(|string| {
+                return string
+                    .iter()
+                    .map(|&byte| text::u8_to_char(byte))
+                    .collect();
+            })
+            .collect();
+    };
+
+    return Names {
+        card_names: decode(&strings[CARD_NAMES_TABLE]),
+        duelist_names: decode(&strings[DUELIST_NAMES_TABLE]),
+    };
+}
+
+/// Write the names of all the cards and duelists into the given slus
+/// file. The names are laid out again in the name pool, see the
+/// `text_pool` module, and the number of bytes left free in it is
+/// returned.
+///
+/// The names are left untouched if they are the same as the ones in
+/// the file, so that the original layout is kept when there is nothing
+/// to change.
+///
+/// Panics if a new name has characters that the game can't display, and
+/// fails if the names don't fit in the pool. Nothing is written in
+/// either case.
+pub fn write_all_names(
+    slus: &mut SlusExecutable,
+    names: &Names,
+) -> Result<usize, LayoutError> {
+    assert!(names.card_names.len() == NUMBER_OF_CARDS);
+    assert!(names.duelist_names.len() == NUMBER_OF_DUELISTS);
+    let original_names = read_all_names(slus);
+    if original_names == *names {
+        return NAME_POOL.free_bytes(slus.as_bytes());
+    }
+
+    // Names that don't change keep their original bytes, since the game
+    // has bytes that we can't encode back.
+    let mut strings = NAME_POOL.read_strings(slus.as_bytes());
+    let owners = [
+        (
+            "card",
+            CARD_NAMES_TABLE,
+            &original_names.card_names,
+            &names.card_names,
+        ),
+        (
+            "duelist",
+            DUELIST_NAMES_TABLE,
+            &original_names.duelist_names,
+            &names.duelist_names,
+        ),
+    ];
+    for (owner, table_index, original_names, new_names) in owners {
+        let table_strings = &mut strings[table_index];
+        for (id, (original_name, name)) in
+            original_names.iter().zip(new_names).enumerate()
+        {
+            if name != original_name {
+                table_strings[id] = encode_name(owner, id, name);
+            }
+        }
+    }
+
+    return NAME_POOL.write_strings(slus.as_bytes_mut(), &strings);
+}
+
+/// Encode a name, without its terminator, panicking with the card or
+/// duelist it belongs to if it can't be encoded.
+fn encode_name(owner: &str, id: usize, name: &str) -> Vec<u8> {
+    let mut encoded_name =
+        text::encode_terminated_string(name).unwrap_or_else(|error| {
+            // Shift id by 1 to match the official number which starts
+            // at 1.
+            panic!("Invalid name for {owner} {}: {error}", id + 1)
+        });
+    encoded_name.pop();
+    return encoded_name;
+}
+
+/// Dump the names of all the cards and duelists into a .csv file at the
+/// given path, with a header and a row per name:
+///
+/// kind,id,name
+///
+/// where kind is either `card` or `duelist`.
+pub fn dump_names_csv(csv_path: &std::path::Path, names: &Names) {
+    let mut csv = Writer::from_path(csv_path).unwrap();
+    csv.write_record(["kind", "id", "name"]).unwrap();
+
+    // Shift the ids by 1 to match the official numbers which start at
+    // 1.
+    for (card_id, name) in names.card_names.iter().enumerate() {
+        csv.write_record(["card", &(card_id + 1).to_string(), name])
+            .unwrap();
+    }
+    for (duelist_id, name) in names.duelist_names.iter().enumerate() {
+        csv.write_record(["duelist", &(duelist_id + 1).to_string(), name])
+            .unwrap();
+    }
+}
+
+/// Load names from a .csv file at the given path, in the format
+/// written by `dump_names_csv`, and update `names` with them. Like the
+/// duelist csv files this one is sparse: the file might be missing, and
+/// it only needs to have rows for the names that the mod changes.
+pub fn load_names_csv(csv_path: &std::path::Path, names: &mut Names) {
+    if !csv_path.try_exists().unwrap() {
+        return;
+    }
+
+    let mut csv = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(csv_path)
+        .unwrap();
+
+    for record_result in csv.records() {
+        let record = record_result.unwrap();
+        let kind = record.get(0).unwrap();
+        let id = record.get(1).unwrap().parse::<usize>().unwrap();
+        let name = record.get(2).unwrap();
+
+        let names = match kind {
+            "card" => &mut names.card_names,
+            "duelist" => &mut names.duelist_names,
+            _ => panic!(
+                "Invalid kind \"{kind}\" in {}, it must be card or duelist",
+                csv_path.display()
+            ),
+        };
+        assert!(
+            (1..=names.len()).contains(&id),
+            "Invalid {kind} id {id} in {}",
+            csv_path.display()
+        );
+
+        // Shift id by 1 to match the index which starts at 0.
+        names[id - 1] = name.to_string();
+    }
+}
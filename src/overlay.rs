@@ -0,0 +1,201 @@
+//! A copy-on-write view of a file. Reads go to the underlying file
+//! until a part of it is written, and writes are kept in memory. This
+//! lets us modify a ROM and then look at only what changed, eg to
+//! create a patch, without copying the whole image first.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Writes are tracked in chunks of this many bytes.
+const CHUNK_SIZE_BYTES: u64 = 4096;
+
+/// A run of bytes that differs from the underlying file, see
+/// `Overlay::changes`.
+pub struct Change {
+    pub offset: u64,
+
+    pub data: Vec<u8>,
+
+    /// The bytes of the underlying file that `data` replaces. Bytes
+    /// past its end are zero.
+    pub base_data: Vec<u8>,
+}
+
+impl Change {
+    /// Offset right after the last byte of the change.
+    pub fn end(&self) -> u64 {
+        return self.offset + self.data.len() as u64;
+    }
+}
+
+pub struct Overlay<F> {
+    base: F,
+    base_len: u64,
+
+    /// Chunks that have been written to, by index. Each one holds the
+    /// whole chunk, including the parts that were not written.
+    chunks: BTreeMap<u64, Vec<u8>>,
+
+    len: u64,
+    position: u64,
+}
+
+impl<F: Read + Seek> Overlay<F> {
+    pub fn new(mut base: F) -> Overlay<F> {
+        let base_len = base.seek(SeekFrom::End(0)).unwrap();
+        return Overlay {
+            base,
+            base_len,
+            chunks: BTreeMap::new(),
+            len: base_len,
+            position: 0,
+        };
+    }
+
+    /// Size of the underlying file, before any writes.
+    pub fn base_size_bytes(&self) -> u64 {
+        return self.base_len;
+    }
+
+    /// Read from the underlying file, ignoring any writes. Bytes past
+    /// the end of the file are read as zero.
+    pub fn read_base(&mut self, offset: u64, buf: &mut [u8]) {
+        buf.fill(0);
+        if offset >= self.base_len {
+            return;
+        }
+
+        let available = (self.base_len - offset).min(buf.len() as u64);
+        self.base.seek(SeekFrom::Start(offset)).unwrap();
+        self.base
+            .read_exact(&mut buf[..available as usize])
+            .unwrap();
+    }
+
+    /// Size of the file, including any writes past the end of the
+    /// underlying file.
+    pub fn size_bytes(&self) -> u64 {
+        return self.len;
+    }
+
+    /// Find every run of consecutive bytes that differs from the
+    /// underlying file, in order. Bytes written past the end of the
+    /// underlying file always count as different, even if they are
+    /// zero, so that applying the changes gives a file of the same
+    /// size.
+    pub fn changes(&mut self) -> Vec<Change> {
+        let mut changes: Vec<Change> = Vec::new();
+
+        let chunk_indices = self.chunks.keys().copied().collect::<Vec<u64>>();
+        for index in chunk_indices {
+            let offset = index * CHUNK_SIZE_BYTES;
+            let size = (self.len - offset).min(CHUNK_SIZE_BYTES) as usize;
+            let mut base_chunk = vec![0; size];
+            self.read_base(offset, &mut base_chunk);
+            let chunk = &self.chunks[&index][..size];
+
+            for (i, (&byte, &base_byte)) in
+                chunk.iter().zip(&base_chunk).enumerate()
+            {
+                let byte_offset = offset + i as u64;
+                if byte == base_byte && byte_offset < self.base_len {
+                    continue;
+                }
+
+                // Extend the previous run if this byte directly follows
+                // it, otherwise start a new one.
+                match changes.last_mut() {
+                    Some(change) if change.end() == byte_offset => {
+                        change.data.push(byte);
+                        change.base_data.push(base_byte);
+                    }
+                    _ => changes.push(Change {
+                        offset: byte_offset,
+                        data: vec![byte],
+                        base_data: vec![base_byte],
+                    }),
+                }
+            }
+        }
+
+        return changes;
+    }
+
+    fn chunk_mut(&mut self, index: u64) -> &mut Vec<u8> {
+        if !self.chunks.contains_key(&index) {
+            let mut chunk = vec![0; CHUNK_SIZE_BYTES as usize];
+            self.read_base(index * CHUNK_SIZE_BYTES, &mut chunk);
+            self.chunks.insert(index, chunk);
+        }
+        return self.chunks.get_mut(&index).unwrap();
+    }
+}
+
+impl<F: Read + Seek> Read for Overlay<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len {
+            return Ok(0);
+        }
+
+        // Read at most up to the end of the current chunk if it was
+        // written, or up to the next written chunk otherwise.
+        let index = self.position / CHUNK_SIZE_BYTES;
+        let start = self.position % CHUNK_SIZE_BYTES;
+        let remaining = (self.len - self.position).min(buf.len() as u64);
+
+        match self.chunks.get(&index) {
+            Some(chunk) => {
+                let size = (CHUNK_SIZE_BYTES - start).min(remaining) as usize;
+                buf[..size].copy_from_slice(&chunk[start as usize..][..size]);
+                self.position += size as u64;
+                return Ok(size);
+            }
+            None => {
+                let next_chunk_offset = match self.chunks.range(index..).next()
+                {
+                    Some((next_index, _)) => next_index * CHUNK_SIZE_BYTES,
+                    None => self.len,
+                };
+                let size =
+                    (next_chunk_offset - self.position).min(remaining) as usize;
+                self.read_base(self.position, &mut buf[..size]);
+                self.position += size as u64;
+                return Ok(size);
+            }
+        }
+    }
+}
+
+impl<F: Read + Seek> Write for Overlay<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let index = self.position / CHUNK_SIZE_BYTES;
+        let start = self.position % CHUNK_SIZE_BYTES;
+        let size = (CHUNK_SIZE_BYTES - start).min(buf.len() as u64) as usize;
+
+        self.chunk_mut(index)[start as usize..][..size]
+            .copy_from_slice(&buf[..size]);
+
+        self.position += size as u64;
+        self.len = self.len.max(self.position);
+        return Ok(size);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl<F> Seek for Overlay<F> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.position = match position {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(offset) => {
+                self.len.checked_add_signed(offset).unwrap()
+            }
+            SeekFrom::Current(offset) => {
+                self.position.checked_add_signed(offset).unwrap()
+            }
+        };
+        return Ok(self.position);
+    }
+}
@@ -0,0 +1,109 @@
+//! Describe what a mod changes, card by card, without writing anything.
+//! This is what `apply` would do to the duelists and the other tables,
+//! in a form that can be reviewed before applying it.
+
+use crate::duelist::{CardList, Duelist};
+
+/// The total of the weights of a valid card list, see
+/// `CardList::is_valid`.
+const TOTAL_RATE: u16 = 2048;
+
+/// A card whose weight differs between two card lists. A weight of zero
+/// means the card isn't in the list, so an old weight of zero is a card
+/// added by the mod and a new weight of zero is a card removed by it.
+pub struct CardRateChange {
+    pub card_id: usize,
+    pub old_rate: u16,
+    pub new_rate: u16,
+}
+
+/// The changes to a single card list of a duelist.
+pub struct CardListChanges {
+    pub duelist_id: usize,
+
+    /// Name of the .csv file of the card list, see
+    /// `Duelist::card_lists`.
+    pub file_name: &'static str,
+
+    pub changes: Vec<CardRateChange>,
+}
+
+/// Chance of a card with the given weight being picked, as a
+/// percentage.
+pub fn rate_percentage(rate: u16) -> f64 {
+    return rate as f64 * 100.0 / TOTAL_RATE as f64;
+}
+
+/// Compare two card lists and return every card whose weight differs,
+/// by card id.
+pub fn compare_card_lists(
+    base_card_list: &CardList,
+    card_list: &CardList,
+) -> Vec<CardRateChange> {
+    let mut changes = Vec::new();
+
+    for (card_id, (&old_rate, &new_rate)) in base_card_list
+        .card_rate
+        .iter()
+        .zip(&card_list.card_rate)
+        .enumerate()
+    {
+        if old_rate != new_rate {
+            changes.push(CardRateChange {
+                card_id,
+                old_rate,
+                new_rate,
+            });
+        }
+    }
+
+    return changes;
+}
+
+/// Compare every card list of every duelist and return the ones that
+/// differ, in the order of the duelists.
+pub fn compare_duelists(
+    base_duelists: &[Duelist],
+    duelists: &[Duelist],
+) -> Vec<CardListChanges> {
+    assert!(base_duelists.len() == duelists.len());
+
+    let mut card_list_changes = Vec::new();
+    for (duelist_id, (base_duelist, duelist)) in
+        base_duelists.iter().zip(duelists).enumerate()
+    {
+        for ((file_name, base_card_list), (_, card_list)) in base_duelist
+            .card_lists()
+            .into_iter()
+            .zip(duelist.card_lists())
+        {
+            let changes = compare_card_lists(base_card_list, card_list);
+            if !changes.is_empty() {
+                card_list_changes.push(CardListChanges {
+                    duelist_id,
+                    file_name,
+                    changes,
+                });
+            }
+        }
+    }
+
+    return card_list_changes;
+}
+
+/// Compare two tables with an entry per card or per duelist, eg the
+/// stats of every card, and return the ids of the entries that differ.
+pub fn compare_entries<T: PartialEq>(
+    base_entries: &[T],
+    entries: &[T],
+) -> Vec<usize> {
+    assert!(base_entries.len() == entries.len());
+
+    return base_entries
+        .iter()
+        .zip(entries)
+        .enumerate()
+        .filter(|(_, (base_entry, entry))| base_entry != entry)
+        .map(|(id, _)| id)
+        .collect();
+}
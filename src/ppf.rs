@@ -0,0 +1,378 @@
+//! PlayStation Patch Format (PPF), the usual format to distribute PS1
+//! mods without distributing the game itself. A patch is a list of
+//! records, each one replacing a run of up to 255 bytes at an offset of
+//! the image file.
+//!
+//! There are three versions of the format, which we can all read. We
+//! only write version 3.0. Every version starts with a 56 byte header:
+//! - 5 bytes signature, `PPF10`, `PPF20` or `PPF30`
+//! - 1 byte encoding method, 0, 1 or 2 respectively
+//! - 50 bytes description, padded with spaces
+//!
+//! Version 1.0 is followed directly by the records, each one being a
+//! 4 byte offset, a 1 byte length and the data.
+//!
+//! Version 2.0 adds a 4 byte size of the image file and 1024 bytes of
+//! the image taken from offset 0x9320, which identify the image the
+//! patch is for. It may end with a file ID, see below.
+//!
+//! Version 3.0 has instead:
+//! - 1 byte image type, 0 for .bin images and 1 for .gi images
+//! - 1 byte flag for the 1024 byte block check
+//! - 1 byte flag for undo data
+//! - 1 byte unused
+//! - 1024 bytes block check, only if enabled. It is taken from offset
+//!   0x9320 for .bin images and 0x80A0 for .gi images.
+//!
+//! Its records have an 8 byte offset, and if undo data is enabled each
+//! record is followed by the bytes it replaces, so that the patch can
+//! be reverted.
+//!
+//! Versions 2.0 and 3.0 may end with a file ID, a short text describing
+//! the patch, usually the contents of a `file_id.diz`. It is stored as
+//! `@BEGIN_FILE_ID.DIZ`, the text, `@END_FILE_ID.DIZ` and the length of
+//! the text, as 4 bytes for version 2.0 and 2 bytes for version 3.0.
+//!
+//! All numbers are little-endian.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::overlay::Overlay;
+
+const DESCRIPTION_OFFSET: usize = 6;
+const DESCRIPTION_SIZE_BYTES: usize = 50;
+const HEADER_SIZE_BYTES: usize = 56;
+
+const PPF1_SIGNATURE: &[u8] = b"PPF10";
+const PPF2_SIGNATURE: &[u8] = b"PPF20";
+const PPF3_SIGNATURE: &[u8] = b"PPF30";
+
+const PPF2_IMAGE_SIZE_OFFSET: usize = 0x38;
+const PPF2_BLOCK_CHECK_OFFSET: usize = 0x3C;
+
+const PPF3_IMAGE_TYPE_OFFSET: usize = 0x38;
+const PPF3_BLOCK_CHECK_FLAG_OFFSET: usize = 0x39;
+const PPF3_UNDO_FLAG_OFFSET: usize = 0x3A;
+const PPF3_BLOCK_CHECK_OFFSET: usize = 0x3C;
+const PPF3_IMAGE_TYPE_BIN: u8 = 0;
+const PPF3_IMAGE_TYPE_GI: u8 = 1;
+
+const BLOCK_CHECK_SIZE_BYTES: usize = 1024;
+const BIN_BLOCK_CHECK_IMAGE_OFFSET: u64 = 0x9320;
+const GI_BLOCK_CHECK_IMAGE_OFFSET: u64 = 0x80A0;
+
+const FILE_ID_BEGIN: &[u8] = b"@BEGIN_FILE_ID.DIZ";
+const FILE_ID_END: &[u8] = b"@END_FILE_ID.DIZ";
+
+/// The longest file ID that the original tools accept.
+const MAX_FILE_ID_SIZE_BYTES: usize = 3072;
+
+const MAX_RECORD_SIZE_BYTES: usize = 255;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PpfVersion {
+    V1,
+    V2,
+    V3,
+}
+
+/// A run of bytes to replace in the image.
+pub struct PatchRecord {
+    /// Offset in the image file.
+    pub offset: u64,
+
+    pub data: Vec<u8>,
+
+    /// The bytes that `data` replaces. Only version 3.0 can store
+    /// these.
+    pub undo_data: Option<Vec<u8>>,
+}
+
+pub struct Patch {
+    pub version: PpfVersion,
+
+    pub description: String,
+
+    /// Offset in the image and contents of the block used to check that
+    /// the patch is applied to the right image.
+    pub block_check: Option<(u64, Vec<u8>)>,
+
+    /// Size of the image file the patch was made for. Only stored by
+    /// version 2.0.
+    pub image_size: Option<u32>,
+
+    pub records: Vec<PatchRecord>,
+
+    pub file_id: Option<String>,
+}
+
+/// Create a version 3.0 patch with every change made to an image
+/// through `overlay`. The block check is always included, because it is
+/// what keeps the patch from being applied to the wrong image.
+///
+/// Bytes written past the end of the original image are always part of
+/// the patch, even if they are zero, so that the patched image ends up
+/// with the same size.
+pub fn create_patch<F: Read + Seek>(
+    overlay: &mut Overlay<F>,
+    description: &str,
+    with_undo_data: bool,
+    file_id: Option<&str>,
+) -> Patch {
+    let mut records = Vec::new();
+    for change in overlay.changes() {
+        let runs = change.data.chunks(MAX_RECORD_SIZE_BYTES);
+        let base_runs = change.base_data.chunks(MAX_RECORD_SIZE_BYTES);
+        for (i, (run, base_run)) in runs.zip(base_runs).enumerate() {
+            records.push(PatchRecord {
+                offset: change.offset + (i * MAX_RECORD_SIZE_BYTES) as u64,
+                data: run.to_vec(),
+                undo_data: with_undo_data.then(|| base_run.to_vec()),
+            });
+        }
+    }
+
+    let mut block = vec![0; BLOCK_CHECK_SIZE_BYTES];
+    overlay.read_base(BIN_BLOCK_CHECK_IMAGE_OFFSET, &mut block);
+
+    return Patch {
+        version: PpfVersion::V3,
+        description: description.to_string(),
+        block_check: Some((BIN_BLOCK_CHECK_IMAGE_OFFSET, block)),
+        image_size: None,
+        records,
+        file_id: file_id.map(|file_id| file_id.to_string()),
+    };
+}
+
+/// Encode a patch in the version 3.0 format. Panics if the patch is not
+/// a version 3.0 patch, or if the file ID or description is too long.
+pub fn encode_patch(patch: &Patch) -> Vec<u8> {
+    assert!(
+        patch.version == PpfVersion::V3,
+        "Only PPF 3.0 patches can be written"
+    );
+    assert!(
+        patch.description.len() <= DESCRIPTION_SIZE_BYTES,
+        "The description of a patch can be at most 50 bytes"
+    );
+
+    let with_undo_data = patch
+        .records
+        .first()
+        .is_some_and(|record| record.undo_data.is_some());
+
+    let mut ppf = Vec::new();
+    ppf.extend_from_slice(PPF3_SIGNATURE);
+    ppf.push(2);
+    let mut description = [b' '; DESCRIPTION_SIZE_BYTES];
+    description[..patch.description.len()]
+        .copy_from_slice(patch.description.as_bytes());
+    ppf.extend_from_slice(&description);
+    ppf.push(match patch.block_check {
+        Some((GI_BLOCK_CHECK_IMAGE_OFFSET, _)) => PPF3_IMAGE_TYPE_GI,
+        _ => PPF3_IMAGE_TYPE_BIN,
+    });
+    ppf.push(patch.block_check.is_some() as u8);
+    ppf.push(with_undo_data as u8);
+    ppf.push(0);
+
+    if let Some((_, block)) = &patch.block_check {
+        assert!(block.len() == BLOCK_CHECK_SIZE_BYTES);
+        ppf.extend_from_slice(block);
+    }
+
+    for record in &patch.records {
+        assert!(record.data.len() <= MAX_RECORD_SIZE_BYTES);
+        assert!(record.undo_data.is_some() == with_undo_data);
+
+        ppf.extend_from_slice(&record.offset.to_le_bytes());
+        ppf.push(record.data.len() as u8);
+        ppf.extend_from_slice(&record.data);
+        if let Some(undo_data) = &record.undo_data {
+            assert!(undo_data.len() == record.data.len());
+            ppf.extend_from_slice(undo_data);
+        }
+    }
+
+    if let Some(file_id) = &patch.file_id {
+        assert!(
+            file_id.len() <= MAX_FILE_ID_SIZE_BYTES,
+            "The file ID of a patch can be at most 3072 bytes"
+        );
+        ppf.extend_from_slice(FILE_ID_BEGIN);
+        ppf.extend_from_slice(file_id.as_bytes());
+        ppf.extend_from_slice(FILE_ID_END);
+        ppf.extend_from_slice(&(file_id.len() as u16).to_le_bytes());
+    }
+
+    return ppf;
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> u32 {
+    return u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+}
+
+fn read_u64_le(data: &[u8], offset: usize) -> u64 {
+    return u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+}
+
+/// Find the file ID at the end of a patch. Return the text and the
+/// offset at which it starts, ie where the records end.
+fn parse_file_id(ppf: &[u8], length_size: usize) -> Option<(String, usize)> {
+    let length_offset = ppf.len().checked_sub(length_size)?;
+    let end_offset = length_offset.checked_sub(FILE_ID_END.len())?;
+    if &ppf[end_offset..length_offset] != FILE_ID_END {
+        return None;
+    }
+
+    let mut length_bytes = [0; 4];
+    length_bytes[..length_size].copy_from_slice(&ppf[length_offset..]);
+    let length = u32::from_le_bytes(length_bytes) as usize;
+
+    let text_offset = end_offset.checked_sub(length)?;
+    let begin_offset = text_offset.checked_sub(FILE_ID_BEGIN.len())?;
+    assert!(
+        &ppf[begin_offset..text_offset] == FILE_ID_BEGIN,
+        "Malformed file ID in patch"
+    );
+
+    let text = String::from_utf8_lossy(&ppf[text_offset..end_offset]);
+    return Some((text.to_string(), begin_offset));
+}
+
+/// Parse a patch in any version of the format. Panics if the patch is
+/// malformed.
+pub fn parse_patch(ppf: &[u8]) -> Patch {
+    assert!(ppf.len() >= HEADER_SIZE_BYTES, "Patch is too short");
+
+    let version = match &ppf[..5] {
+        PPF1_SIGNATURE => PpfVersion::V1,
+        PPF2_SIGNATURE => PpfVersion::V2,
+        PPF3_SIGNATURE => PpfVersion::V3,
+        _ => panic!("Not a PPF patch"),
+    };
+    let description = String::from_utf8_lossy(
+        &ppf[DESCRIPTION_OFFSET..][..DESCRIPTION_SIZE_BYTES],
+    )
+    .trim_end()
+    .to_string();
+
+    let mut patch = Patch {
+        version,
+        description,
+        block_check: None,
+        image_size: None,
+        records: Vec::new(),
+        file_id: None,
+    };
+
+    let mut records_end = ppf.len();
+    let (records_start, offset_size, with_undo_data) = match version {
+        PpfVersion::V1 => (HEADER_SIZE_BYTES, 4, false),
+        PpfVersion::V2 => {
+            patch.image_size = Some(read_u32_le(ppf, PPF2_IMAGE_SIZE_OFFSET));
+            patch.block_check = Some((
+                BIN_BLOCK_CHECK_IMAGE_OFFSET,
+                ppf[PPF2_BLOCK_CHECK_OFFSET..][..BLOCK_CHECK_SIZE_BYTES]
+                    .to_vec(),
+            ));
+            if let Some((file_id, start)) = parse_file_id(ppf, 4) {
+                patch.file_id = Some(file_id);
+                records_end = start;
+            }
+            (PPF2_BLOCK_CHECK_OFFSET + BLOCK_CHECK_SIZE_BYTES, 4, false)
+        }
+        PpfVersion::V3 => {
+            let mut records_start = PPF3_BLOCK_CHECK_OFFSET;
+            if ppf[PPF3_BLOCK_CHECK_FLAG_OFFSET] != 0 {
+                let image_offset = match ppf[PPF3_IMAGE_TYPE_OFFSET] {
+                    PPF3_IMAGE_TYPE_GI => GI_BLOCK_CHECK_IMAGE_OFFSET,
+                    _ => BIN_BLOCK_CHECK_IMAGE_OFFSET,
+                };
+                patch.block_check = Some((
+                    image_offset,
+                    ppf[records_start..][..BLOCK_CHECK_SIZE_BYTES].to_vec(),
+                ));
+                records_start += BLOCK_CHECK_SIZE_BYTES;
+            }
+            if let Some((file_id, start)) = parse_file_id(ppf, 2) {
+                patch.file_id = Some(file_id);
+                records_end = start;
+            }
+            (records_start, 8, ppf[PPF3_UNDO_FLAG_OFFSET] != 0)
+        }
+    };
+
+    let mut offset = records_start;
+    while offset < records_end {
+        let record_offset = match offset_size {
+            4 => read_u32_le(ppf, offset) as u64,
+            _ => read_u64_le(ppf, offset),
+        };
+        let length = ppf[offset + offset_size] as usize;
+        let data_offset = offset + offset_size + 1;
+        offset = data_offset + length;
+
+        let data = ppf[data_offset..offset].to_vec();
+        let undo_data = if with_undo_data {
+            offset += length;
+            Some(ppf[offset - length..offset].to_vec())
+        }
+        else {
+            None
+        };
+
+        patch.records.push(PatchRecord {
+            offset: record_offset,
+            data,
+            undo_data,
+        });
+    }
+    assert!(offset == records_end, "Truncated record in patch");
+
+    return patch;
+}
+
+/// Apply a patch to an image file, or revert it if `undo` is set.
+///
+/// Panics without modifying the image if the block check of the patch
+/// doesn't match the image, or if `undo` is set and the patch has no
+/// undo data. The block check is skipped when reverting, because
+/// applying the patch might have changed the block.
+pub fn apply_patch<F: Read + Write + Seek>(
+    image: &mut F,
+    patch: &Patch,
+    undo: bool,
+) {
+    if undo {
+        assert!(
+            patch
+                .records
+                .iter()
+                .all(|record| record.undo_data.is_some()),
+            "The patch has no undo data"
+        );
+    }
+    else if let Some((offset, block)) = &patch.block_check {
+        let mut image_block = vec![0; BLOCK_CHECK_SIZE_BYTES];
+        image.seek(SeekFrom::Start(*offset)).unwrap();
+        image.read_exact(&mut image_block).unwrap();
+        assert!(
+            &image_block == block,
+            "The patch was made for a different image"
+        );
+    }
+
+    for record in &patch.records {
+        let data = if undo {
+            record.undo_data.as_ref().unwrap()
+        }
+        else {
+            &record.data
+        };
+
+        image.seek(SeekFrom::Start(record.offset)).unwrap();
+        image.write_all(data).unwrap();
+    }
+}
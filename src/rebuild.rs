@@ -0,0 +1,144 @@
+//! Rebuild a ROM image with some of its files replaced. This is what
+//! allows files to change size, which can't be done by overwriting
+//! sectors in place.
+//!
+//! The original image is used as a template. Its system area, volume
+//! descriptors, path tables, directories and all unchanged files are
+//! kept exactly as they are. Only the sectors of the replaced files,
+//! the directory records that point to them and the volume size are
+//! regenerated.
+
+use std::collections::HashMap;
+use std::io::{Read, Seek, Write};
+
+use crate::image::{self, RomImage};
+use crate::{iso9660, sector};
+
+/// Number of sectors and bytes a file will occupy, as counted in its
+/// directory record. Form 2 files are handled as whole Mode 2 sectors,
+/// but their directory records still count 2048 bytes per sector.
+fn file_size(form: iso9660::SectorForm, contents: &[u8]) -> (usize, usize) {
+    return match form {
+        iso9660::SectorForm::Form1 => (
+            contents.len().div_ceil(sector::DATA_SIZE_BYTES),
+            contents.len(),
+        ),
+        iso9660::SectorForm::Form2 => {
+            let size_sectors = contents.len() / sector::MODE2_SIZE_BYTES;
+            (size_sectors, size_sectors * sector::DATA_SIZE_BYTES)
+        }
+    };
+}
+
+/// Rebuild the image in place with the files in `new_files` replaced.
+/// The keys are paths in the form used by `iso9660::FileEntry::path`,
+/// the values are the new contents in the form returned by
+/// `image::read_file`.
+///
+/// A file that still fits in the sectors it originally occupied is
+/// written in place. A file that grew is moved after the last sector
+/// used by the filesystem, so that all other files keep their original
+/// location. This matters because PS1 games commonly load data by
+/// sector number without going through the filesystem. Sectors that
+/// came after the last file in the original image, eg padding, are
+/// moved after the relocated files.
+///
+/// Panics if a path in `new_files` doesn't exist in the image or is a
+/// directory.
+pub fn rebuild_image<F: Read + Write + Seek>(
+    rom: &mut RomImage<F>,
+    new_files: &HashMap<String, Vec<u8>>,
+) {
+    let entries = iso9660::read_filesystem(rom);
+
+    for path in new_files.keys() {
+        assert!(
+            entries
+                .iter()
+                .any(|entry| &entry.path == path && !entry.is_directory),
+            "{path} is not a file in the ROM"
+        );
+    }
+
+    let end_of_files = entries
+        .iter()
+        .map(|entry| entry.extent_sector + entry.size_sectors())
+        .max()
+        .unwrap();
+
+    let relocated_sectors = entries
+        .iter()
+        .filter_map(|entry| {
+            let contents = new_files.get(&entry.path)?;
+            let (size_sectors, _) = file_size(entry.form, contents);
+            return (size_sectors > entry.size_sectors())
+                .then_some(size_sectors);
+        })
+        .sum::<usize>();
+
+    // Make room for the relocated files first. The trailing sectors are
+    // moved starting from the last one, so that none of them is
+    // overwritten before it has been moved.
+    if relocated_sectors > 0 {
+        for sector_index in (end_of_files..rom.sector_count()).rev() {
+            let mut raw_sector = rom.read_sector(sector_index);
+            let new_index = sector_index + relocated_sectors;
+            sector::readdress_sector(&mut raw_sector, new_index);
+            rom.write_sector(new_index, &raw_sector);
+        }
+
+        let volume_size = iso9660::read_volume_size(rom);
+        iso9660::write_volume_size(rom, volume_size + relocated_sectors);
+    }
+
+    let mut next_free_sector = end_of_files;
+
+    for entry in &entries {
+        let Some(contents) = new_files.get(&entry.path)
+        else {
+            continue;
+        };
+
+        let (size_sectors, size_bytes) = file_size(entry.form, contents);
+        let extent_sector = if size_sectors <= entry.size_sectors() {
+            entry.extent_sector
+        }
+        else {
+            let extent_sector = next_free_sector;
+            next_free_sector += size_sectors;
+            extent_sector
+        };
+
+        image::write_file_sectors(rom, extent_sector, entry.form, contents);
+        iso9660::write_file_record(rom, entry, extent_sector, size_bytes);
+    }
+}
+
+/// Load the files of an extracted filesystem tree, as written by
+/// `iso9660::extract_all_files`, and return the ones that differ from
+/// the files in the image. The result can be passed to
+/// `rebuild_image`.
+///
+/// Any file might be missing from the tree, in which case the original
+/// is kept. Files in the tree that don't exist in the image are
+/// ignored, because we can't add new entries to the directories.
+pub fn load_changed_files<F: Read + Seek>(
+    rom: &mut RomImage<F>,
+    files_dir: &std::path::Path,
+) -> HashMap<String, Vec<u8>> {
+    let mut changed_files = HashMap::new();
+
+    for entry in iso9660::read_filesystem(rom) {
+        let file_path = files_dir.join(&entry.path);
+        if entry.is_directory || !file_path.try_exists().unwrap() {
+            continue;
+        }
+
+        let contents = std::fs::read(&file_path).unwrap();
+        if contents != image::read_file(rom, &entry) {
+            changed_files.insert(entry.path, contents);
+        }
+    }
+
+    return changed_files;
+}
@@ -0,0 +1,306 @@
+//! Module to manipulate the ritual table, which says which monsters
+//! each ritual card needs and which monster it summons. The function
+//! names follow the same convention as the duelist module:
+//! - read/write if they operate on the ROM file
+//! - load/dump if they operate on csv files
+//!
+//! The table is in the wa_mrg file, at `RITUAL_TABLE_OFFSET`, right
+//! before the equip table. It is a list of 10 byte entries, each with
+//! the card numbers of the ritual card, the three monsters it needs and
+//! the monster it summons, in 2 bytes each. The list ends with a ritual
+//! card number of zero. All numbers are little-endian.
+//!
+//! Unlike the rest of the tables, I haven't been able to check the
+//! location of this one against a retail dump yet. If it is wrong,
+//! reading the table panics on the first value that isn't a card
+//! number, and writing it overwrites 0x200 bytes of unrelated data. So
+//! `dump` and `apply` leave the rituals alone until the location is
+//! verified.
+
+use csv::{ReaderBuilder, Writer};
+
+use crate::card::{Card, CardKind};
+use crate::duelist::NUMBER_OF_CARDS;
+use crate::image::WaMrgArchive;
+
+pub const RITUAL_TABLE_OFFSET: usize = 0xB84E00;
+
+/// The space reserved for the ritual table, up to the start of the
+/// equip table.
+pub const RITUAL_TABLE_SIZE: usize = 0x200;
+
+const RITUAL_ENTRY_SIZE: usize = 10;
+
+/// A ritual. All the cards are card ids, which start at 0.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Ritual {
+    pub ritual_card: usize,
+
+    /// The monsters that have to be on the field to perform the ritual.
+    pub monsters: [usize; 3],
+
+    /// The monster that the ritual summons.
+    pub result: usize,
+}
+
+/// Every ritual, in the order they are stored in.
+#[derive(PartialEq, Eq)]
+pub struct RitualTable {
+    pub rituals: Vec<Ritual>,
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> usize {
+    let low_byte: usize = data[offset].into();
+    let high_byte: usize = data[offset + 1].into();
+    return (high_byte << 8) + low_byte;
+}
+
+fn write_u16_le(data: &mut Vec<u8>, value: usize) {
+    data.push(value as u8);
+    data.push((value >> 8) as u8);
+}
+
+/// Convert a card number read from the ritual table to a card id.
+fn read_card_id(card_number: usize) -> usize {
+    assert!(
+        (1..=NUMBER_OF_CARDS).contains(&card_number),
+        "Invalid card number {card_number} in the ritual table"
+    );
+    return card_number - 1;
+}
+
+/// Read the ritual table from the given wa_mrg file.
+pub fn read_ritual_table(wa_mrg: &WaMrgArchive) -> RitualTable {
+    let table = &wa_mrg.as_bytes()
+        [RITUAL_TABLE_OFFSET..RITUAL_TABLE_OFFSET + RITUAL_TABLE_SIZE];
+    let mut rituals = Vec::new();
+
+    for entry in table.chunks_exact(RITUAL_ENTRY_SIZE) {
+        let ritual_number = read_u16_le(entry, 0);
+        if ritual_number == 0 {
+            break;
+        }
+
+        rituals.push(Ritual {
+            ritual_card: read_card_id(ritual_number),
+            monsters: [
+                read_card_id(read_u16_le(entry, 2)),
+                read_card_id(read_u16_le(entry, 4)),
+                read_card_id(read_u16_le(entry, 6)),
+            ],
+            result: read_card_id(read_u16_le(entry, 8)),
+        });
+    }
+
+    return RitualTable { rituals };
+}
+
+/// Check that every card of every ritual exists and is of the right
+/// kind: the ritual card must be a ritual and the rest must be
+/// monsters. Each ritual card must also appear only once. Return a
+/// description of every problem found.
+pub fn validate_ritual_table(
+    ritual_table: &RitualTable,
+    cards: &[Card],
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut check_card = |card_id: usize, kind: CardKind, role: &str| {
+        // Shift the ids by 1 to match the official numbers which start
+        // at 1.
+        if card_id >= NUMBER_OF_CARDS {
+            errors.push(format!("{role} {} doesn't exist", card_id + 1));
+        }
+        else if cards[card_id].kind() != kind {
+            errors.push(format!(
+                "{role} {} is a {} card, not a {} card",
+                card_id + 1,
+                cards[card_id].kind().name(),
+                kind.name()
+            ));
+        }
+    };
+
+    for ritual in &ritual_table.rituals {
+        check_card(ritual.ritual_card, CardKind::Ritual, "Ritual card");
+        for &monster in &ritual.monsters {
+            check_card(monster, CardKind::Monster, "Ritual monster");
+        }
+        check_card(ritual.result, CardKind::Monster, "Ritual result");
+    }
+
+    for (index, ritual) in ritual_table.rituals.iter().enumerate() {
+        let is_repeated = ritual_table.rituals[..index]
+            .iter()
+            .any(|other| other.ritual_card == ritual.ritual_card);
+        if is_repeated {
+            errors.push(format!(
+                "Ritual card {} has more than one ritual",
+                ritual.ritual_card + 1
+            ));
+        }
+    }
+
+    return errors;
+}
+
+/// Write the ritual table into the given wa_mrg file.
+///
+/// The table is left untouched if it is the same as the one in the
+/// file, so that the original layout is kept when there is nothing to
+/// change.
+///
+/// Panics if the table is invalid, see `validate_ritual_table`, or if
+/// the encoded table doesn't fit in `RITUAL_TABLE_SIZE`.
+pub fn write_ritual_table(
+    wa_mrg: &mut WaMrgArchive,
+    ritual_table: &RitualTable,
+    cards: &[Card],
+) {
+    if read_ritual_table(wa_mrg) == *ritual_table {
+        return;
+    }
+
+    let errors = validate_ritual_table(ritual_table, cards);
+    assert!(
+        errors.is_empty(),
+        "Invalid ritual table:\n- {}",
+        errors.join("\n- ")
+    );
+
+    let mut table_data = Vec::new();
+    for ritual in &ritual_table.rituals {
+        // Shift the ids by 1 to get the card numbers, which start at 1.
+        write_u16_le(&mut table_data, ritual.ritual_card + 1);
+        for &monster in &ritual.monsters {
+            write_u16_le(&mut table_data, monster + 1);
+        }
+        write_u16_le(&mut table_data, ritual.result + 1);
+    }
+    write_u16_le(&mut table_data, 0);
+
+    assert!(
+        table_data.len() <= RITUAL_TABLE_SIZE,
+        "The ritual table takes {} bytes, but only {RITUAL_TABLE_SIZE} are \
+         reserved for it",
+        table_data.len()
+    );
+
+    let table = &mut wa_mrg.as_bytes_mut()
+        [RITUAL_TABLE_OFFSET..RITUAL_TABLE_OFFSET + RITUAL_TABLE_SIZE];
+    table[..table_data.len()].copy_from_slice(&table_data);
+    table[table_data.len()..].fill(0);
+}
+
+/// Dump the ritual table into a .csv file at the given path, with a row
+/// per ritual. The columns are the ritual card, the three monsters and
+/// the result, followed by the names of the same cards. The names are
+/// only there to help the user.
+pub fn dump_rituals_csv(
+    csv_path: &std::path::Path,
+    ritual_table: &RitualTable,
+    card_names: &[String],
+) {
+    let mut csv = Writer::from_path(csv_path).unwrap();
+    csv.write_record([
+        "ritual_id",
+        "monster_1_id",
+        "monster_2_id",
+        "monster_3_id",
+        "result_id",
+        "ritual_name",
+        "monster_1_name",
+        "monster_2_name",
+        "monster_3_name",
+        "result_name",
+    ])
+    .unwrap();
+
+    for ritual in &ritual_table.rituals {
+        let card_ids = [
+            ritual.ritual_card,
+            ritual.monsters[0],
+            ritual.monsters[1],
+            ritual.monsters[2],
+            ritual.result,
+        ];
+
+        // Shift the ids by 1 to match the official numbers which start
+        // at 1.
+        let mut record = Vec::new();
+        for card_id in card_ids {
+            record.push((card_id + 1).to_string());
+        }
+        for card_id in card_ids {
+            record.push(card_names[card_id].clone());
+        }
+        csv.write_record(&record).unwrap();
+    }
+}
+
+/// Parse an official card number from a field of rituals.csv and
+/// convert it to a card id. The number is checked against
+/// `NUMBER_OF_CARDS` later, by `validate_ritual_table`.
+fn parse_card_number(csv_path: &std::path::Path, field: &str) -> usize {
+    let card_number = field.parse::<usize>().unwrap_or(0);
+    assert!(
+        card_number >= 1,
+        "Invalid card number \"{field}\" in {}",
+        csv_path.display()
+    );
+    return card_number - 1;
+}
+
+/// Load rituals from a .csv file at the given path, in the format
+/// written by `dump_rituals_csv`, and update `ritual_table` with them.
+/// Like the duelist csv files this one is sparse: the file might be
+/// missing, and rituals that don't appear in it are kept as they are.
+/// A row for a ritual card that already has a ritual replaces it, and
+/// a row for any other card adds a new ritual. To remove a ritual, give
+/// its ritual card a row with the other fields empty.
+pub fn load_rituals_csv(
+    csv_path: &std::path::Path,
+    ritual_table: &mut RitualTable,
+) {
+    if !csv_path.try_exists().unwrap() {
+        return;
+    }
+
+    let mut csv = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(csv_path)
+        .unwrap();
+
+    for record_result in csv.records() {
+        let record = record_result.unwrap();
+        let field = |index: usize| {
+            return record.get(index).unwrap_or("");
+        };
+
+        let ritual_card = parse_card_number(csv_path, field(0));
+        let existing_index = ritual_table
+            .rituals
+            .iter()
+            .position(|ritual| ritual.ritual_card == ritual_card);
+
+        if (1..=4).all(|index| field(index).is_empty()) {
+            if let Some(index) = existing_index {
+                ritual_table.rituals.remove(index);
+            }
+            continue;
+        }
+
+        let ritual = Ritual {
+            ritual_card,
+            monsters: [
+                parse_card_number(csv_path, field(1)),
+                parse_card_number(csv_path, field(2)),
+                parse_card_number(csv_path, field(3)),
+            ],
+            result: parse_card_number(csv_path, field(4)),
+        };
+        match existing_index {
+            Some(index) => ritual_table.rituals[index] = ritual,
+            None => ritual_table.rituals.push(ritual),
+        }
+    }
+}
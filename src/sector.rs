@@ -0,0 +1,430 @@
+//! Layout of a single CD-ROM/XA sector. The game uses Mode 2 sectors,
+//! which come in two forms. I don't know exactly how they work but
+//! they seem to have the following layout:
+//! - 12 bytes sync pattern
+//! - 3 bytes address
+//! - 1 byte mode
+//! - 8 bytes subheader
+//! - Form 1:
+//!   - 2048 bytes data
+//!   - 4 bytes error detection
+//!   - 276 bytes error correction
+//! - Form 2:
+//!   - 2324 bytes data
+//!   - 4 bytes error detection
+//!
+//! The error detection code (EDC) is a CRC-32 of the subheader and the
+//! data. The error correction code (ECC) is a pair of Reed-Solomon
+//! product codes, P and Q, calculated over the header, subheader, data
+//! and EDC. For Mode 2 the header is treated as zero when calculating
+//! the ECC, so that the parity doesn't depend on the sector's address.
+//! Emulators are mostly lenient about these, but real hardware and
+//! strict tools are not, so we regenerate both for every sector we
+//! write.
+
+use crc;
+
+pub const SECTOR_SIZE_BYTES: usize = 2352;
+
+pub const SYNC_PATTERN: [u8; 12] = [
+    0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+];
+const MODE_OFFSET_BYTES: usize = 15;
+const MODE_2: u8 = 2;
+
+// The address in the header counts from the start of the disc, which
+// includes a 2 second (150 sector) pregap before the data track.
+const PREGAP_SECTORS: usize = 150;
+const SECTORS_PER_SECOND: usize = 75;
+
+// The subheader is 4 bytes, stored twice: file number, channel number,
+// submode and coding information.
+const SUBHEADER_OFFSET_BYTES: usize = 16;
+const SUBHEADER_SIZE_BYTES: usize = 4;
+pub const SUBMODE_OFFSET_BYTES: usize = 18;
+
+pub const SUBMODE_END_OF_RECORD: u8 = 0x01;
+pub const SUBMODE_DATA: u8 = 0x08;
+pub const SUBMODE_FORM2: u8 = 0x20;
+pub const SUBMODE_END_OF_FILE: u8 = 0x80;
+
+pub const MODE2_OFFSET_BYTES: usize = 16;
+pub const MODE2_SIZE_BYTES: usize = 2336;
+
+pub const DATA_OFFSET_BYTES: usize = 24;
+pub const DATA_SIZE_BYTES: usize = 2048;
+
+const CRC_OFFSET_BYTES: usize = 2072;
+const FORM2_CRC_OFFSET_BYTES: usize = 2348;
+
+const HEADER_OFFSET_BYTES: usize = 12;
+const HEADER_SIZE_BYTES: usize = 4;
+
+const ECC_P_OFFSET_BYTES: usize = 2076;
+const ECC_Q_OFFSET_BYTES: usize = 2248;
+
+/// Calculate the EDC of a sector. For Form 1 this covers the subheader
+/// and the 2048 bytes of data, for Form 2 the subheader and the 2324
+/// bytes of data.
+fn calculate_crc(raw_data: &[u8]) -> u32 {
+    const CUSTOM_ALG: crc::Algorithm<u32> = crc::Algorithm {
+        width: 32,
+        poly: 0x8001801b,
+        init: 0x0,
+        refin: true,
+        refout: true,
+        xorout: 0x0,
+        // I'm not sure what these last two are, but they don't seem to
+        // be part of the calculation. They are probably either for
+        // testing or for use on the receiving side.
+        check: 0x0,
+        residue: 0x0,
+    };
+
+    let crc = crc::Crc::<u32>::new(&CUSTOM_ALG);
+    let mut digest = crc.digest();
+    digest.update(raw_data);
+    return digest.finalize();
+}
+
+/// Lookup tables for multiplication in GF(2^8) with the polynomial
+/// x^8 + x^4 + x^3 + x^2 + 1, which is what the CD-ROM Reed-Solomon
+/// codes use. `ECC_F_LUT[i]` is `i * 2` and `ECC_B_LUT[i ^ (i * 2)]` is
+/// `i`, ie it divides by 3.
+const ECC_F_LUT: [u8; 256] = {
+    let mut lut = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let doubled = (i << 1) ^ (if i & 0x80 != 0 { 0x11D } else { 0 });
+        lut[i] = doubled as u8;
+        i += 1;
+    }
+    lut
+};
+
+const ECC_B_LUT: [u8; 256] = {
+    let mut lut = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        lut[i ^ ECC_F_LUT[i] as usize] = i as u8;
+        i += 1;
+    }
+    lut
+};
+
+/// Calculate one of the two Reed-Solomon parity blocks. `ecc_data` is
+/// the sector starting at the header, viewed as a matrix of
+/// `major_count` columns and `minor_count` rows of bytes. Each column
+/// is walked with a stride of `minor_increment`, wrapping around at the
+/// end of the block, and produces two parity bytes which are written at
+/// `major` and `major + major_count` of `parity`.
+///
+/// The P parity uses 86 columns of 24 bytes going straight down. The Q
+/// parity uses 52 diagonals of 43 bytes, and it includes the P parity
+/// in its input.
+fn calculate_ecc_block(
+    ecc_data: &[u8],
+    major_count: usize,
+    minor_count: usize,
+    major_multiplier: usize,
+    minor_increment: usize,
+    parity: &mut [u8],
+) {
+    let size = major_count * minor_count;
+    assert!(parity.len() == 2 * major_count);
+
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_multiplier + (major & 1);
+        let mut ecc_a: u8 = 0;
+        let mut ecc_b: u8 = 0;
+
+        for _ in 0..minor_count {
+            let byte = ecc_data[index];
+            index += minor_increment;
+            if index >= size {
+                index -= size;
+            }
+            ecc_a ^= byte;
+            ecc_b ^= byte;
+            ecc_a = ECC_F_LUT[ecc_a as usize];
+        }
+
+        ecc_a = ECC_B_LUT[(ECC_F_LUT[ecc_a as usize] ^ ecc_b) as usize];
+        parity[major] = ecc_a;
+        parity[major + major_count] = ecc_a ^ ecc_b;
+    }
+}
+
+/// Calculate the P and Q parity of a Mode 2 Form 1 sector and write
+/// them into the error correction area of the sector. The EDC must
+/// already be up to date, because it is covered by the parity.
+fn write_ecc_to_sector(sector: &mut [u8]) {
+    assert!(sector.len() == SECTOR_SIZE_BYTES);
+
+    // Mode 2 calculates the parity as if the header was zeroed out.
+    let header_range =
+        HEADER_OFFSET_BYTES..HEADER_OFFSET_BYTES + HEADER_SIZE_BYTES;
+    let mut header = [0; HEADER_SIZE_BYTES];
+    header.copy_from_slice(&sector[header_range.clone()]);
+    sector[header_range.clone()].fill(0);
+
+    let mut p_parity = [0; ECC_Q_OFFSET_BYTES - ECC_P_OFFSET_BYTES];
+    calculate_ecc_block(
+        &sector[HEADER_OFFSET_BYTES..ECC_P_OFFSET_BYTES],
+        86,
+        24,
+        2,
+        86,
+        &mut p_parity,
+    );
+    sector[ECC_P_OFFSET_BYTES..ECC_Q_OFFSET_BYTES].copy_from_slice(&p_parity);
+
+    let mut q_parity = [0; SECTOR_SIZE_BYTES - ECC_Q_OFFSET_BYTES];
+    calculate_ecc_block(
+        &sector[HEADER_OFFSET_BYTES..ECC_Q_OFFSET_BYTES],
+        52,
+        43,
+        86,
+        88,
+        &mut q_parity,
+    );
+    sector[ECC_Q_OFFSET_BYTES..].copy_from_slice(&q_parity);
+
+    sector[header_range].copy_from_slice(&header);
+}
+
+/// Convert a number in `0..100` to binary-coded decimal.
+fn to_bcd(value: usize) -> u8 {
+    assert!(value < 100);
+    return ((value / 10) << 4 | (value % 10)) as u8;
+}
+
+/// Write the sync pattern and the header of a Mode 2 sector. The header
+/// holds the absolute address of the sector in minutes, seconds and
+/// sectors, each stored as BCD.
+pub fn write_sector_header(sector: &mut [u8], sector_index: usize) {
+    sector[..HEADER_OFFSET_BYTES].copy_from_slice(&SYNC_PATTERN);
+    sector[HEADER_OFFSET_BYTES..MODE_OFFSET_BYTES]
+        .copy_from_slice(&sector_address(sector_index));
+    sector[MODE_OFFSET_BYTES] = MODE_2;
+}
+
+/// The address of a sector as stored in its header.
+fn sector_address(sector_index: usize) -> [u8; 3] {
+    let address = sector_index + PREGAP_SECTORS;
+    let minutes = address / (60 * SECTORS_PER_SECOND);
+    let seconds = address / SECTORS_PER_SECOND % 60;
+    let sectors = address % SECTORS_PER_SECOND;
+
+    return [to_bcd(minutes), to_bcd(seconds), to_bcd(sectors)];
+}
+
+/// Write the subheader of a sector. Both copies are written.
+pub fn write_subheader(sector: &mut [u8], subheader: &[u8]) {
+    assert!(subheader.len() == SUBHEADER_SIZE_BYTES);
+
+    let start = SUBHEADER_OFFSET_BYTES;
+    let middle = start + SUBHEADER_SIZE_BYTES;
+    sector[start..middle].copy_from_slice(subheader);
+    sector[middle..middle + SUBHEADER_SIZE_BYTES].copy_from_slice(subheader);
+}
+
+/// Regenerate the EDC of a Form 2 sector. Form 2 has no ECC.
+pub fn write_form2_edc(sector: &mut [u8]) {
+    let crc =
+        calculate_crc(&sector[SUBHEADER_OFFSET_BYTES..FORM2_CRC_OFFSET_BYTES]);
+    sector[FORM2_CRC_OFFSET_BYTES..].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Move a sector to a new address. The header is the only part of a
+/// Mode 2 sector that depends on its address, so the EDC and ECC stay
+/// valid.
+pub fn readdress_sector(sector: &mut [u8], sector_index: usize) {
+    assert!(sector.len() == SECTOR_SIZE_BYTES);
+    write_sector_header(sector, sector_index);
+}
+
+/// Mark a sector as the last one of a file, which is also the end of a
+/// record.
+pub fn mark_end_of_file(sector: &mut [u8]) {
+    let mut subheader = [0; SUBHEADER_SIZE_BYTES];
+    subheader.copy_from_slice(
+        &sector[SUBHEADER_OFFSET_BYTES..][..SUBHEADER_SIZE_BYTES],
+    );
+    subheader[SUBMODE_OFFSET_BYTES - SUBHEADER_OFFSET_BYTES] |=
+        SUBMODE_END_OF_RECORD | SUBMODE_END_OF_FILE;
+    write_subheader(sector, &subheader);
+
+    let data = sector[DATA_OFFSET_BYTES..][..DATA_SIZE_BYTES].to_vec();
+    write_data_to_sector(&data, sector);
+}
+
+/// Build a Form 1 sector from scratch, with the given submode and data.
+pub fn build_form1_sector(
+    sector_index: usize,
+    submode: u8,
+    raw_data: &[u8],
+) -> Vec<u8> {
+    let mut sector = vec![0; SECTOR_SIZE_BYTES];
+    write_sector_header(&mut sector, sector_index);
+    write_subheader(&mut sector, &[0, 0, submode, 0]);
+    write_data_to_sector(raw_data, &mut sector);
+    return sector;
+}
+
+/// Build a sector from scratch out of a 2336 byte Mode 2 sector, ie
+/// subheader, data and EDC/ECC. The subheader decides whether it gets
+/// Form 1 EDC and ECC or Form 2 EDC.
+pub fn build_mode2_sector(sector_index: usize, mode2_data: &[u8]) -> Vec<u8> {
+    assert!(mode2_data.len() == MODE2_SIZE_BYTES);
+
+    let mut sector = vec![0; SECTOR_SIZE_BYTES];
+    write_sector_header(&mut sector, sector_index);
+    sector[MODE2_OFFSET_BYTES..].copy_from_slice(mode2_data);
+
+    if sector[SUBMODE_OFFSET_BYTES] & SUBMODE_FORM2 != 0 {
+        write_form2_edc(&mut sector);
+    }
+    else {
+        let data = sector[DATA_OFFSET_BYTES..][..DATA_SIZE_BYTES].to_vec();
+        write_data_to_sector(&data, &mut sector);
+    }
+
+    return sector;
+}
+
+/// Get the raw data from a single CD-ROM/XA Form 1 sector. Usually you
+/// don't want to operate on a single sector. This function's main
+/// intent is to be called by other functions that will work on spans of
+/// sectors.
+pub fn read_data_from_sector(sector: &[u8]) -> &[u8] {
+    assert!(sector.len() == SECTOR_SIZE_BYTES);
+    return &sector[DATA_OFFSET_BYTES..DATA_OFFSET_BYTES + DATA_SIZE_BYTES];
+}
+
+/// Write the raw data into a single CD-ROM/XA Form 1 sector. This
+/// writes the payload and regenerates the EDC and ECC. The header and
+/// subheader are left as they are.
+///
+/// If the raw data is smaller than the payload size of the sector, the
+/// rest will be zeroed out. If the raw data is larger, the function
+/// panics.
+///
+/// Usually you don't want to operate on a single sector. This
+/// function's main intent is to be called by other functions that will
+/// work on spans of sectors.
+pub fn write_data_to_sector(raw_data: &[u8], sector: &mut [u8]) {
+    assert!(raw_data.len() <= DATA_SIZE_BYTES);
+    assert!(sector.len() == SECTOR_SIZE_BYTES);
+
+    let data =
+        &mut sector[DATA_OFFSET_BYTES..DATA_OFFSET_BYTES + DATA_SIZE_BYTES];
+    data[..raw_data.len()].copy_from_slice(raw_data);
+    data[raw_data.len()..].fill(0);
+
+    let crc_segment =
+        &sector[SUBHEADER_OFFSET_BYTES..DATA_OFFSET_BYTES + DATA_SIZE_BYTES];
+    let crc = calculate_crc(crc_segment);
+
+    sector[CRC_OFFSET_BYTES] = crc as u8;
+    sector[CRC_OFFSET_BYTES + 1] = (crc >> 8) as u8;
+    sector[CRC_OFFSET_BYTES + 2] = (crc >> 16) as u8;
+    sector[CRC_OFFSET_BYTES + 3] = (crc >> 24) as u8;
+
+    write_ecc_to_sector(sector);
+}
+
+/// A problem found in a sector by `check_sector`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SectorError {
+    /// The sector doesn't start with the sync pattern.
+    Sync,
+
+    /// The address in the header doesn't match the position of the
+    /// sector in the track.
+    Address,
+
+    /// The mode byte is not 2.
+    Mode,
+
+    /// The two copies of the subheader differ.
+    Subheader,
+
+    /// The EDC doesn't match the subheader and data.
+    Edc,
+
+    /// The P or Q parity doesn't match the rest of the sector. Only
+    /// Form 1 sectors have ECC.
+    Ecc,
+}
+
+impl std::fmt::Display for SectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let description = match self {
+            SectorError::Sync => "bad sync pattern",
+            SectorError::Address => "wrong address in header",
+            SectorError::Mode => "not a Mode 2 sector",
+            SectorError::Subheader => "subheader copies differ",
+            SectorError::Edc => "bad EDC",
+            SectorError::Ecc => "bad ECC",
+        };
+        return write!(f, "{description}");
+    }
+}
+
+/// Check every part of a raw sector that can be verified on its own
+/// and return the problems found, if any. The form of the sector is
+/// taken from the first copy of the subheader.
+///
+/// Form 2 sectors are allowed to have an EDC of zero, which means that
+/// it wasn't calculated.
+pub fn check_sector(sector: &[u8], sector_index: usize) -> Vec<SectorError> {
+    assert!(sector.len() == SECTOR_SIZE_BYTES);
+
+    let mut errors = Vec::new();
+
+    if sector[..HEADER_OFFSET_BYTES] != SYNC_PATTERN {
+        errors.push(SectorError::Sync);
+    }
+    if sector[HEADER_OFFSET_BYTES..MODE_OFFSET_BYTES]
+        != sector_address(sector_index)
+    {
+        errors.push(SectorError::Address);
+    }
+    if sector[MODE_OFFSET_BYTES] != MODE_2 {
+        errors.push(SectorError::Mode);
+    }
+
+    let subheader = &sector[SUBHEADER_OFFSET_BYTES..][..SUBHEADER_SIZE_BYTES];
+    let subheader_copy = &sector
+        [SUBHEADER_OFFSET_BYTES + SUBHEADER_SIZE_BYTES..]
+        [..SUBHEADER_SIZE_BYTES];
+    if subheader != subheader_copy {
+        errors.push(SectorError::Subheader);
+    }
+
+    if sector[SUBMODE_OFFSET_BYTES] & SUBMODE_FORM2 != 0 {
+        let edc = &sector[FORM2_CRC_OFFSET_BYTES..];
+        let crc = calculate_crc(
+            &sector[SUBHEADER_OFFSET_BYTES..FORM2_CRC_OFFSET_BYTES],
+        );
+        if edc != [0; 4] && edc != crc.to_le_bytes() {
+            errors.push(SectorError::Edc);
+        }
+    }
+    else {
+        let crc =
+            calculate_crc(&sector[SUBHEADER_OFFSET_BYTES..CRC_OFFSET_BYTES]);
+        if sector[CRC_OFFSET_BYTES..ECC_P_OFFSET_BYTES] != crc.to_le_bytes() {
+            errors.push(SectorError::Edc);
+        }
+
+        let mut regenerated = sector.to_vec();
+        write_ecc_to_sector(&mut regenerated);
+        if sector[ECC_P_OFFSET_BYTES..] != regenerated[ECC_P_OFFSET_BYTES..] {
+            errors.push(SectorError::Ecc);
+        }
+    }
+
+    return errors;
+}
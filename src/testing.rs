@@ -6,20 +6,23 @@
 //! mod, I can be sure of what the expected output of each function
 //! should be and I can revisit unit testing.
 
+use std::io::{Read, Seek, Write};
+
+use crate::image::RomImage;
 use crate::*;
 
 /// Does a simple passthrough of the game data to verify that we can
 /// read & write the image without errors and without accidentally
 /// changing something. The output file should be the identical to the
 /// input file.
-pub fn passthrough_test(rom_file: &mut Vec<u8>) {
-    let slus = image::read_slus_from_bin(rom_file);
-    let mut wa_mrg = image::read_wa_mrg_from_bin(rom_file);
+pub fn passthrough_test<F: Read + Write + Seek>(rom: &mut RomImage<F>) {
+    let slus = image::read_slus_from_bin(rom);
+    let mut wa_mrg = image::read_wa_mrg_from_bin(rom);
 
     let duelist_info = duelist::read_all_duelists(&slus, &wa_mrg);
 
     duelist::write_all_duelists(&mut wa_mrg, &duelist_info);
 
-    image::write_slus_to_bin(rom_file, &slus);
-    image::write_wa_mrg_to_bin(rom_file, &wa_mrg);
+    image::write_slus_to_bin(rom, &slus);
+    image::write_wa_mrg_to_bin(rom, &wa_mrg);
 }
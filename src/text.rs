@@ -1,108 +1,149 @@
-/// Text conversion helpers.
-///
-/// I'm not sure what format YGO:FM uses, so I've just hard-coded it. If
-/// it turns out to be some standard encoding, we can significantly
-/// improve this code.
+//! Text conversion helpers.
+//!
+//! I'm not sure what format YGO:FM uses, so I've just hard-coded it. If
+//! it turns out to be some standard encoding, we can significantly
+//! improve this code.
+
+pub const STRING_TERMINATOR: u8 = 255;
+
+/// A character that can't be represented in the game's encoding.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EncodeError {
+    pub character: char,
 
-const STRING_TERMINATOR: u8 = 255;
+    /// Index of the character in the string, counted in `char`s.
+    pub position: usize,
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(
+            f,
+            "The game can't display the character '{}' at position {}",
+            self.character, self.position
+        );
+    }
+}
+
+/// The bytes the game uses for each character. A few characters have
+/// more than one byte: 'a' is both 0x03 and 0x55, '<' is both 0x27 and
+/// 0x54 and '>' is both 0x28 and 0x51. They look the same in the text
+/// we've checked, so they decode to the same `char`. When encoding, the
+/// first byte in this table is used, which is always the lower one.
+const CHARACTER_TABLE: [(u8, char); 85] = [
+    (0x18, 'A'),
+    (0x2D, 'B'),
+    (0x2B, 'C'),
+    (0x20, 'D'),
+    (0x25, 'E'),
+    (0x31, 'F'),
+    (0x29, 'G'),
+    (0x23, 'H'),
+    (0x1A, 'I'),
+    (0x3B, 'J'),
+    (0x33, 'K'),
+    (0x2A, 'L'),
+    (0x1E, 'M'),
+    (0x2C, 'N'),
+    (0x21, 'O'),
+    (0x2F, 'P'),
+    (0x3E, 'Q'),
+    (0x26, 'R'),
+    (0x1D, 'S'),
+    (0x1C, 'T'),
+    (0x35, 'U'),
+    (0x39, 'V'),
+    (0x22, 'W'),
+    (0x46, 'X'),
+    (0x24, 'Y'),
+    (0x3F, 'Z'),
+    (0x03, 'a'),
+    (0x15, 'b'),
+    (0x0F, 'c'),
+    (0x0C, 'd'),
+    (0x01, 'e'),
+    (0x13, 'f'),
+    (0x10, 'g'),
+    (0x09, 'h'),
+    (0x05, 'i'),
+    (0x34, 'j'),
+    (0x16, 'k'),
+    (0x0A, 'l'),
+    (0x0E, 'm'),
+    (0x06, 'n'),
+    (0x04, 'o'),
+    (0x14, 'p'),
+    (0x37, 'q'),
+    (0x08, 'r'),
+    (0x07, 's'),
+    (0x02, 't'),
+    (0x0D, 'u'),
+    (0x19, 'v'),
+    (0x12, 'w'),
+    (0x36, 'x'),
+    (0x11, 'y'),
+    (0x32, 'z'),
+    (0x38, '0'),
+    (0x3D, '1'),
+    (0x3A, '2'),
+    (0x41, '3'),
+    (0x4A, '4'),
+    (0x42, '5'),
+    (0x4E, '6'),
+    (0x45, '7'),
+    (0x57, '8'),
+    (0x59, '9'),
+    (0x00, ' '),
+    (0x30, '-'),
+    (0x3C, '#'),
+    (0x43, '&'),
+    (0x0B, '.'),
+    (0x1F, ','),
+    (0x55, 'a'),
+    (0x17, '!'),
+    (0x1B, '\''),
+    (0x27, '<'),
+    (0x28, '>'),
+    (0x2E, '?'),
+    (0x44, '/'),
+    (0x48, ':'),
+    (0x4B, ')'),
+    (0x4C, '('),
+    (0x4F, '$'),
+    (0x50, '*'),
+    (0x51, '>'),
+    (0x54, '<'),
+    (0x40, '"'),
+    (0x56, '+'),
+    (0x5B, '%'),
+];
 
 /// Convert a single byte to `char`. This is probably redundant since
 /// code outside this module would most likely prefer to work directly
 /// with Strings. This might be made private in the future.
+///
+/// Bytes that aren't in the table are converted to '_', which is why
+/// '_' itself can't be encoded. Use `decode_tokens` to keep them.
 pub fn u8_to_char(byte: u8) -> char {
     // I've verified that this is correct for decoding card text in the US
     // version (SLUS-01411). I tried it on the EU version (SLES-03947) and
     // it seems that some characters are correct, but not all. It seems that
     // mods are generally based on SLUS-01411 so let's support only that for
     // now and we'll see about SLES-03947 later.
-    match byte {
-        0x18 => 'A',
-        0x2D => 'B',
-        0x2B => 'C',
-        0x20 => 'D',
-        0x25 => 'E',
-        0x31 => 'F',
-        0x29 => 'G',
-        0x23 => 'H',
-        0x1A => 'I',
-        0x3B => 'J',
-        0x33 => 'K',
-        0x2A => 'L',
-        0x1E => 'M',
-        0x2C => 'N',
-        0x21 => 'O',
-        0x2F => 'P',
-        0x3E => 'Q',
-        0x26 => 'R',
-        0x1D => 'S',
-        0x1C => 'T',
-        0x35 => 'U',
-        0x39 => 'V',
-        0x22 => 'W',
-        0x46 => 'X',
-        0x24 => 'Y',
-        0x3F => 'Z',
-        0x03 => 'a',
-        0x15 => 'b',
-        0x0F => 'c',
-        0x0C => 'd',
-        0x01 => 'e',
-        0x13 => 'f',
-        0x10 => 'g',
-        0x09 => 'h',
-        0x05 => 'i',
-        0x34 => 'j',
-        0x16 => 'k',
-        0x0A => 'l',
-        0x0E => 'm',
-        0x06 => 'n',
-        0x04 => 'o',
-        0x14 => 'p',
-        0x37 => 'q',
-        0x08 => 'r',
-        0x07 => 's',
-        0x02 => 't',
-        0x0D => 'u',
-        0x19 => 'v',
-        0x12 => 'w',
-        0x36 => 'x',
-        0x11 => 'y',
-        0x32 => 'z',
-        0x38 => '0',
-        0x3D => '1',
-        0x3A => '2',
-        0x41 => '3',
-        0x4A => '4',
-        0x42 => '5',
-        0x4E => '6',
-        0x45 => '7',
-        0x57 => '8',
-        0x59 => '9',
-        0x00 => ' ',
-        0x30 => '-',
-        0x3C => '#',
-        0x43 => '&',
-        0x0B => '.',
-        0x1F => ',',
-        0x55 => 'a',
-        0x17 => '!',
-        0x1B => '\'',
-        0x27 => '<',
-        0x28 => '>',
-        0x2E => '?',
-        0x44 => '/',
-        0x48 => ':',
-        0x4B => ')',
-        0x4C => '(',
-        0x4F => '$',
-        0x50 => '*',
-        0x51 => '>',
-        0x54 => '<',
-        0x40 => '"',
-        0x56 => '+',
-        0x5B => '%',
-        _ => '_',
-    }
+    return CHARACTER_TABLE
+        .iter()
+        .find(|(table_byte, _)| *table_byte == byte)
+        .map_or('_', |(_, character)| *character);
+}
+
+/// Convert a `char` to the byte the game uses for it, or None if the
+/// game has no byte for it. Characters with more than one byte get the
+/// first one in `CHARACTER_TABLE`.
+pub fn char_to_u8(character: char) -> Option<u8> {
+    return CHARACTER_TABLE
+        .iter()
+        .find(|(_, table_character)| *table_character == character)
+        .map(|(byte, _)| *byte);
 }
 
 /// Read bytes from the start of the slice until the string terminator
@@ -115,10 +156,14 @@ pub fn u8_to_char(byte: u8) -> char {
 /// something like this:
 ///
 /// ```
-/// let binary_data = ...; // This can be as large as the entire ROM
-/// let offset = ...;      // Somehow determine where your string starts
+/// # use fmde::text::read_terminated_string;
+/// // This can be as large as the entire ROM
+/// let binary_data = [0x00, 0x18, 0x15, 0x0F, 0xFF, 0x2D];
+/// // Somehow determine where your string starts
+/// let offset = 1;
 ///
-/// let my_string = read_terminated_string(binary_data[offset..]);
+/// let my_string = read_terminated_string(&binary_data[offset..]);
+/// assert_eq!(my_string, "Abc");
 /// ```
 ///
 /// The function requires that the string terminator byte exists in the
@@ -138,3 +183,341 @@ pub fn read_terminated_string(binary_data: &[u8]) -> String {
 
     panic!("No terminator character in buffer!");
 }
+
+/// Convert a string to the game's encoding, followed by the string
+/// terminator. This is the reverse of `read_terminated_string`.
+///
+/// Returns an error for the first character that the game has no byte
+/// for.
+pub fn encode_terminated_string(string: &str) -> Result<Vec<u8>, EncodeError> {
+    let mut result = Vec::new();
+
+    for (position, character) in string.chars().enumerate() {
+        match char_to_u8(character) {
+            Some(byte) => result.push(byte),
+            None => {
+                return Err(EncodeError {
+                    character,
+                    position,
+                });
+            }
+        }
+    }
+
+    result.push(STRING_TERMINATOR);
+    return Ok(result);
+}
+
+/// The byte that starts a new line in longer text, like card
+/// descriptions.
+pub const NEW_LINE: u8 = 0xFE;
+
+/// The byte that seems to change the color of the text that follows,
+/// with the color number in the next byte. This is what the card
+/// descriptions we've looked at suggest, but it hasn't been checked in
+/// the game, so until it is both bytes are kept as unknown bytes,
+/// rather than as a token of their own.
+pub const COLOR: u8 = 0xF8;
+
+/// A piece of the game's text. Unlike `read_terminated_string`, a list
+/// of tokens keeps every byte of the text, so it can be written back
+/// exactly as it was read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    Char(char),
+    NewLine,
+
+    /// A byte we don't know the meaning of. The bytes that share a
+    /// character with a lower byte, see `CHARACTER_TABLE`, are also
+    /// unknown, so that they are written back as they were.
+    Unknown(u8),
+}
+
+/// Markup that can't be converted to tokens.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MarkupError {
+    /// A character that the game has no byte for.
+    Character(EncodeError),
+
+    /// A `{...}` code that isn't one of the codes that
+    /// `render_markup` writes. `position` is the index of its `{` in
+    /// the markup, counted in `char`s.
+    InvalidCode { code: String, position: usize },
+
+    /// A `{` without a matching `}`.
+    UnclosedCode { position: usize },
+}
+
+impl std::fmt::Display for MarkupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return match self {
+            MarkupError::Character(error) => error.fmt(f),
+            MarkupError::InvalidCode { code, position } => {
+                write!(f, "Invalid code {{{code}}} at position {position}")
+            }
+            MarkupError::UnclosedCode { position } => {
+                write!(f, "The code at position {position} has no closing }}")
+            }
+        };
+    }
+}
+
+/// Convert text from the game, without its terminator, to tokens.
+pub fn decode_tokens(binary_data: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut bytes = binary_data.iter().copied();
+
+    while let Some(byte) = bytes.next() {
+        let token = if byte == NEW_LINE {
+            Token::NewLine
+        }
+        else if byte == COLOR {
+            // Keep the color number as a raw byte as well, rather than
+            // as whatever character it happens to be.
+            tokens.push(Token::Unknown(byte));
+            match bytes.next() {
+                Some(color) => Token::Unknown(color),
+                None => continue,
+            }
+        }
+        else {
+            let character = u8_to_char(byte);
+            if char_to_u8(character) == Some(byte) {
+                Token::Char(character)
+            }
+            else {
+                Token::Unknown(byte)
+            }
+        };
+        tokens.push(token);
+    }
+
+    return tokens;
+}
+
+/// Convert tokens to the game's encoding, without a terminator. This is
+/// the reverse of `decode_tokens`.
+///
+/// Returns an error for the first character that the game has no byte
+/// for, with the index of its token as the position.
+pub fn encode_tokens(tokens: &[Token]) -> Result<Vec<u8>, EncodeError> {
+    let mut result = Vec::new();
+
+    for (position, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::Char(character) => match char_to_u8(character) {
+                Some(byte) => result.push(byte),
+                None => {
+                    return Err(EncodeError {
+                        character,
+                        position,
+                    });
+                }
+            },
+            Token::NewLine => result.push(NEW_LINE),
+            Token::Unknown(byte) => {
+                assert!(
+                    byte != STRING_TERMINATOR,
+                    "The string terminator can't be part of a string"
+                );
+                result.push(byte);
+            }
+        }
+    }
+
+    return Ok(result);
+}
+
+/// Convert tokens to readable text. Characters are written as they are,
+/// and everything else as a code in braces, which the game has no
+/// characters for:
+/// - `{nl}` for a new line
+/// - `{byte:F0}` for an unknown byte, in hexadecimal
+pub fn render_markup(tokens: &[Token]) -> String {
+    let mut result = String::new();
+
+    for token in tokens {
+        match token {
+            Token::Char(character) => result.push(*character),
+            Token::NewLine => result.push_str("{nl}"),
+            Token::Unknown(byte) => {
+                result.push_str(&format!("{{byte:{byte:02X}}}"))
+            }
+        }
+    }
+
+    return result;
+}
+
+/// Convert text written by `render_markup` back to tokens.
+pub fn parse_markup(markup: &str) -> Result<Vec<Token>, MarkupError> {
+    let mut tokens = Vec::new();
+    let mut characters = markup.chars().enumerate();
+
+    while let Some((position, character)) = characters.next() {
+        if character != '{' {
+            if char_to_u8(character).is_none() {
+                return Err(MarkupError::Character(EncodeError {
+                    character,
+                    position,
+                }));
+            }
+            tokens.push(Token::Char(character));
+            continue;
+        }
+
+        let mut code = String::new();
+        loop {
+            match characters.next() {
+                Some((_, '}')) => break,
+                Some((_, character)) => code.push(character),
+                None => return Err(MarkupError::UnclosedCode { position }),
+            }
+        }
+
+        let token = match code.split_once(':') {
+            None if code == "nl" => Some(Token::NewLine),
+            Some(("byte", byte)) => u8::from_str_radix(byte, 16)
+                .ok()
+                .filter(|&byte| byte != STRING_TERMINATOR)
+                .map(Token::Unknown),
+            _ => None,
+        };
+        match token {
+            Some(token) => tokens.push(token),
+            None => return Err(MarkupError::InvalidCode { code, position }),
+        }
+    }
+
+    return Ok(tokens);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_byte_round_trips_to_its_canonical_byte() {
+        for (byte, character) in CHARACTER_TABLE {
+            assert_eq!(u8_to_char(byte), character);
+
+            let canonical_byte = char_to_u8(character).unwrap();
+            assert_eq!(u8_to_char(canonical_byte), character);
+            assert!(canonical_byte <= byte);
+        }
+    }
+
+    #[test]
+    fn conflicting_characters_encode_to_the_lower_byte() {
+        assert_eq!(u8_to_char(0x03), 'a');
+        assert_eq!(u8_to_char(0x55), 'a');
+        assert_eq!(char_to_u8('a'), Some(0x03));
+
+        assert_eq!(u8_to_char(0x27), '<');
+        assert_eq!(u8_to_char(0x54), '<');
+        assert_eq!(char_to_u8('<'), Some(0x27));
+
+        assert_eq!(u8_to_char(0x28), '>');
+        assert_eq!(u8_to_char(0x51), '>');
+        assert_eq!(char_to_u8('>'), Some(0x28));
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        let string: String = CHARACTER_TABLE
+            .iter()
+            .map(|(_, character)| character)
+            .collect();
+        let encoded = encode_terminated_string(&string).unwrap();
+
+        assert_eq!(encoded.last(), Some(&STRING_TERMINATOR));
+        assert_eq!(read_terminated_string(&encoded), string);
+    }
+
+    #[test]
+    fn empty_string_is_only_the_terminator() {
+        assert_eq!(encode_terminated_string(""), Ok(vec![STRING_TERMINATOR]));
+    }
+
+    #[test]
+    fn unknown_characters_are_errors() {
+        assert_eq!(
+            encode_terminated_string("Blue-Eyes é"),
+            Err(EncodeError {
+                character: 'é',
+                position: 10
+            })
+        );
+        // Unknown bytes decode to '_', but '_' has no byte of its own.
+        assert_eq!(u8_to_char(0xF0), '_');
+        assert_eq!(
+            encode_terminated_string("a_b"),
+            Err(EncodeError {
+                character: '_',
+                position: 1
+            })
+        );
+    }
+
+    #[test]
+    fn every_byte_round_trips_through_tokens() {
+        let mut binary_data = Vec::new();
+        for byte in 0..STRING_TERMINATOR {
+            binary_data.push(byte);
+            // Follow every byte with each kind of byte, so that color
+            // codes are followed by every kind of byte.
+            binary_data.push(0x18);
+            binary_data.push(byte);
+            binary_data.push(NEW_LINE);
+        }
+        binary_data.push(COLOR);
+
+        let tokens = decode_tokens(&binary_data);
+        assert_eq!(encode_tokens(&tokens), Ok(binary_data));
+        assert_eq!(parse_markup(&render_markup(&tokens)), Ok(tokens));
+    }
+
+    #[test]
+    fn control_bytes_decode_to_tokens() {
+        let tokens = decode_tokens(&[0x18, NEW_LINE, COLOR, 3, 0x55, 0xF0]);
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Char('A'),
+                Token::NewLine,
+                Token::Unknown(COLOR),
+                Token::Unknown(3),
+                Token::Unknown(0x55),
+                Token::Unknown(0xF0),
+            ]
+        );
+        assert_eq!(
+            render_markup(&tokens),
+            "A{nl}{byte:F8}{byte:03}{byte:55}{byte:F0}"
+        );
+    }
+
+    #[test]
+    fn invalid_markup_is_an_error() {
+        assert_eq!(
+            parse_markup("ab{nl"),
+            Err(MarkupError::UnclosedCode { position: 2 })
+        );
+        for code in ["newline", "color:3", "byte:FF", "byte:", "nl:1"] {
+            assert_eq!(
+                parse_markup(&format!("a{{{code}}}")),
+                Err(MarkupError::InvalidCode {
+                    code: code.to_string(),
+                    position: 1
+                })
+            );
+        }
+        assert_eq!(
+            parse_markup("a_"),
+            Err(MarkupError::Character(EncodeError {
+                character: '_',
+                position: 1
+            }))
+        );
+    }
+}
@@ -0,0 +1,389 @@
+//! Module to lay out pools of text, the regions of the game files where
+//! strings are stored one after the other. Other data never points to
+//! the strings directly, but through tables of 2 byte little-endian
+//! pointers, relative to the start of the pool.
+//!
+//! To change the strings, all of them are collected from the pointer
+//! tables and laid out again from the start of the space that the
+//! original strings take up. Strings that are the same are stored only
+//! once, and so are strings that are the end of another string, which
+//! point into it instead, since a pointer can land in the middle of a
+//! string and share its terminator. This means that every table that
+//! points into the pool must be known: a string that nothing known
+//! points to would be overwritten. Tables that might point into the
+//! pool, but that we can't tell the strings of, are checked instead,
+//! and laying out the pool fails if any of their pointers lands in that
+//! space.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::text::STRING_TERMINATOR;
+
+/// A table of `count` pointers into a text pool, at `offset` in the
+/// file.
+pub struct PointerTable {
+    pub offset: usize,
+    pub count: usize,
+}
+
+/// Why the strings of a pool can't be laid out again.
+#[derive(Debug)]
+pub enum LayoutError {
+    /// The strings take `size` bytes once laid out, but the pool only
+    /// has `space` bytes for them.
+    TooBig {
+        name: &'static str,
+        size: usize,
+        space: usize,
+    },
+
+    /// A pointer table at `table_offset` is inside the space that the
+    /// strings take up, from `start` to `end`.
+    TableInPool {
+        name: &'static str,
+        table_offset: usize,
+        start: usize,
+        end: usize,
+    },
+
+    /// The pointer at `pointer_offset`, from `unknown_pointers`, points
+    /// into the space that the strings take up, from `start` to `end`.
+    UnknownPointer {
+        name: &'static str,
+        pointer_offset: usize,
+        start: usize,
+        end: usize,
+    },
+}
+
+impl std::fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return match self {
+            LayoutError::TooBig { name, size, space } => write!(
+                f,
+                "The {name} take {size} bytes, but there are only {space} \
+                 bytes of space for them"
+            ),
+            LayoutError::TableInPool {
+                name,
+                table_offset,
+                start,
+                end,
+            } => write!(
+                f,
+                "The pointer table at {table_offset:#X} is inside the {name} \
+                 at {start:#X}..{end:#X}"
+            ),
+            LayoutError::UnknownPointer {
+                name,
+                pointer_offset,
+                start,
+                end,
+            } => write!(
+                f,
+                "The pointer at {pointer_offset:#X} points into the {name} at \
+                 {start:#X}..{end:#X}, but we don't know which string it is, \
+                 so the {name} can't be laid out again"
+            ),
+        };
+    }
+}
+
+/// A pool of text and every pointer table that points into it.
+pub struct TextPool {
+    /// What the strings are, for error messages, eg "names".
+    pub name: &'static str,
+
+    /// The offset in the file that the pointers are relative to.
+    pub base_offset: usize,
+    pub pointer_tables: &'static [PointerTable],
+
+    /// Tables that look like pointers into the pool, but that we don't
+    /// know the strings of. They are left as they are, so none of them
+    /// can point into the space that the strings take up.
+    pub unknown_pointers: &'static [PointerTable],
+}
+
+impl TextPool {
+    /// Return the absolute offset of the string that the pointer at
+    /// `index` of `table` points to.
+    fn read_pointer(
+        &self,
+        data: &[u8],
+        table: &PointerTable,
+        index: usize,
+    ) -> usize {
+        assert!(index < table.count);
+        let low_byte: usize = data[table.offset + 2 * index].into();
+        let high_byte: usize = data[table.offset + 2 * index + 1].into();
+        return self.base_offset + (high_byte << 8) + low_byte;
+    }
+
+    fn write_pointer(
+        &self,
+        data: &mut [u8],
+        table: &PointerTable,
+        index: usize,
+        absolute_offset: usize,
+    ) {
+        let relative_offset = absolute_offset - self.base_offset;
+        assert!(
+            relative_offset <= 0xFFFF,
+            "A string at offset {absolute_offset:#X} is out of reach of the \
+             pointers of its text pool"
+        );
+        data[table.offset + 2 * index] = relative_offset as u8;
+        data[table.offset + 2 * index + 1] = (relative_offset >> 8) as u8;
+    }
+
+    /// Read every string in the pool, without their terminators. There
+    /// is a list of strings for each pointer table, in the same order
+    /// as `pointer_tables`.
+    pub fn read_strings(&self, data: &[u8]) -> Vec<Vec<Vec<u8>>> {
+        let mut strings = Vec::new();
+        for table in self.pointer_tables {
+            let mut table_strings = Vec::new();
+            for index in 0..table.count {
+                let offset = self.read_pointer(data, table, index);
+                let size = string_size(&data[offset..]);
+                table_strings.push(data[offset..offset + size].to_vec());
+            }
+            strings.push(table_strings);
+        }
+        return strings;
+    }
+
+    /// Return the start and end offsets of the space that the strings
+    /// of the pool take up, from the first byte of the first string to
+    /// the terminator of the last one.
+    fn region(&self, data: &[u8]) -> (usize, usize) {
+        let mut start = usize::MAX;
+        let mut end = 0;
+        for table in self.pointer_tables {
+            for index in 0..table.count {
+                let offset = self.read_pointer(data, table, index);
+                start = start.min(offset);
+                end = end.max(offset + string_size(&data[offset..]) + 1);
+            }
+        }
+        return (start, end);
+    }
+
+    /// Check that the strings can be laid out again in the region from
+    /// `start` to `end`: no pointer table may be inside it, and no
+    /// pointer of `unknown_pointers` may point into it.
+    fn check_region(
+        &self,
+        data: &[u8],
+        start: usize,
+        end: usize,
+    ) -> Result<(), LayoutError> {
+        for table in self.pointer_tables.iter().chain(self.unknown_pointers) {
+            if table.offset + 2 * table.count > start && table.offset < end {
+                return Err(LayoutError::TableInPool {
+                    name: self.name,
+                    table_offset: table.offset,
+                    start,
+                    end,
+                });
+            }
+        }
+
+        for table in self.unknown_pointers {
+            for index in 0..table.count {
+                let offset = self.read_pointer(data, table, index);
+                if (start..end).contains(&offset) {
+                    return Err(LayoutError::UnknownPointer {
+                        name: self.name,
+                        pointer_offset: table.offset + 2 * index,
+                        start,
+                        end,
+                    });
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Lay out the given strings one after the other, each with its
+    /// terminator, and return the laid out bytes along with the offset
+    /// of each string in them, in the same format as `strings`. Strings
+    /// that are the same, or that are the end of another string, aren't
+    /// stored again.
+    fn lay_out(strings: &[Vec<Vec<u8>>]) -> (Vec<u8>, Vec<Vec<usize>>) {
+        let mut suffixes = HashSet::new();
+        for string in strings.iter().flatten() {
+            for start in 1..=string.len() {
+                suffixes.insert(&string[start..]);
+            }
+        }
+
+        let mut data = Vec::new();
+        let mut string_offsets = HashMap::new();
+        for string in strings.iter().flatten() {
+            if suffixes.contains(string.as_slice())
+                || string_offsets.contains_key(string.as_slice())
+            {
+                continue;
+            }
+
+            for start in 0..=string.len() {
+                string_offsets
+                    .entry(&string[start..])
+                    .or_insert(data.len() + start);
+            }
+            data.extend_from_slice(string);
+            data.push(STRING_TERMINATOR);
+        }
+
+        let offsets = strings
+            .iter()
+            .map(|table_strings| {
+                return table_strings
+                    .iter()
+                    .map(|string| string_offsets[string.as_slice()])
+                    .collect();
+            })
+            .collect();
+        return (data, offsets);
+    }
+
+    /// Return the number of bytes that would be left free if the
+    /// strings in `data` were laid out again.
+    ///
+    /// Fails if they wouldn't fit in the space that they take up now.
+    /// This can't happen with strings that were laid out by the game or
+    /// by `write_strings`, but the error is reported rather than
+    /// trusted.
+    pub fn free_bytes(&self, data: &[u8]) -> Result<usize, LayoutError> {
+        let (start, end) = self.region(data);
+        let (laid_out_data, _) = Self::lay_out(&self.read_strings(data));
+        return (end - start).checked_sub(laid_out_data.len()).ok_or(
+            LayoutError::TooBig {
+                name: self.name,
+                size: laid_out_data.len(),
+                space: end - start,
+            },
+        );
+    }
+
+    /// Lay out the given strings in the pool and rewrite every pointer
+    /// to match. `strings` must be in the format returned by
+    /// `read_strings`. The space that is left over is filled with
+    /// terminators. Return the number of bytes left free.
+    ///
+    /// Fails if the strings don't fit in the space that the strings in
+    /// `data` take up, or if that space can't be laid out again, see
+    /// `unknown_pointers`. Nothing is written in either case.
+    pub fn write_strings(
+        &self,
+        data: &mut [u8],
+        strings: &[Vec<Vec<u8>>],
+    ) -> Result<usize, LayoutError> {
+        assert!(strings.len() == self.pointer_tables.len());
+        for (table, table_strings) in self.pointer_tables.iter().zip(strings) {
+            assert!(table_strings.len() == table.count);
+        }
+
+        let (start, end) = self.region(data);
+        self.check_region(data, start, end)?;
+        let (laid_out_data, offsets) = Self::lay_out(strings);
+        if laid_out_data.len() > end - start {
+            return Err(LayoutError::TooBig {
+                name: self.name,
+                size: laid_out_data.len(),
+                space: end - start,
+            });
+        }
+
+        let offset = start + laid_out_data.len();
+        data[start..offset].copy_from_slice(&laid_out_data);
+        data[offset..end].fill(STRING_TERMINATOR);
+        for (table, table_offsets) in self.pointer_tables.iter().zip(offsets) {
+            for (index, string_offset) in table_offsets.into_iter().enumerate()
+            {
+                self.write_pointer(data, table, index, start + string_offset);
+            }
+        }
+
+        return Ok(end - offset);
+    }
+}
+
+/// Return the size of the string at the start of `data`, without its
+/// terminator.
+fn string_size(data: &[u8]) -> usize {
+    return data
+        .iter()
+        .position(|&byte| byte == STRING_TERMINATOR)
+        .expect("No terminator character in buffer!");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pool of 4 strings, with its pointer table at the start of the
+    /// data and the strings right after it.
+    const POOL: TextPool = TextPool {
+        name: "strings",
+        base_offset: 0,
+        pointer_tables: &[PointerTable {
+            offset: 0,
+            count: 4,
+        }],
+        unknown_pointers: &[],
+    };
+    const STRINGS_OFFSET: usize = 8;
+
+    /// Return the data of `POOL` with the given strings, each with its
+    /// terminator, and the pointers at the given offsets into them.
+    fn pool_data(strings: &[u8], pointers: [usize; 4]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for pointer in pointers {
+            let offset = STRINGS_OFFSET + pointer;
+            data.extend_from_slice(&(offset as u16).to_le_bytes());
+        }
+        data.extend_from_slice(strings);
+        return data;
+    }
+
+    /// Return the strings of `POOL` in `data`.
+    fn read(data: &[u8]) -> Vec<Vec<u8>> {
+        return POOL.read_strings(data).pop().unwrap();
+    }
+
+    #[test]
+    fn strings_that_share_their_ends_fit_again() {
+        // "ABC", "BC", the empty string and "ABC" again, all in the 4
+        // bytes of "ABC".
+        let mut data = pool_data(&[1, 2, 3, STRING_TERMINATOR], [0, 1, 3, 0]);
+        let strings = read(&data);
+        assert_eq!(strings, [vec![1, 2, 3], vec![2, 3], vec![], vec![1, 2, 3]]);
+        assert_eq!(POOL.free_bytes(&data).unwrap(), 0);
+
+        let original_data = data.clone();
+        assert_eq!(POOL.write_strings(&mut data, &[strings]).unwrap(), 0);
+        assert_eq!(data, original_data);
+    }
+
+    #[test]
+    fn strings_that_dont_fit_are_an_error() {
+        let mut data = pool_data(&[1, 2, 3, STRING_TERMINATOR], [0, 1, 3, 0]);
+        let original_data = data.clone();
+        let mut strings = read(&data);
+        strings[1] = vec![4];
+
+        let error = POOL.write_strings(&mut data, &[strings]).unwrap_err();
+        assert!(matches!(
+            error,
+            LayoutError::TooBig {
+                size: 6,
+                space: 4,
+                ..
+            }
+        ));
+        assert_eq!(data, original_data);
+    }
+}
@@ -0,0 +1,510 @@
+//! VCDIFF, the generic delta format of RFC 3284, which is what xdelta
+//! patches use. A patch is a header followed by a sequence of windows.
+//! Each window produces the next part of the target file out of a
+//! segment of the source file (or of the target produced so far) with
+//! three kinds of instructions:
+//! - ADD: bytes stored in the patch
+//! - COPY: bytes from the source segment or earlier in the window
+//! - RUN: a single byte stored in the patch, repeated
+//!
+//! The instructions, the bytes they add and the addresses they copy
+//! from are stored in three separate sections of each window. Each
+//! instruction is a byte indexing a code table, which gives the type,
+//! size and address mode of up to two instructions. We only support
+//! the default code table.
+//!
+//! Numbers are variable length, 7 bits per byte with the most
+//! significant bits first and every byte except the last marked by the
+//! top bit.
+//!
+//! xdelta compresses the sections of each window by default, which is
+//! an extension we don't support. Such patches have to be created with
+//! `xdelta3 -S none`. We do support xdelta's per-window Adler-32
+//! checksum.
+
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+
+use crate::overlay::Overlay;
+
+pub const SIGNATURE: &[u8] = &[0xD6, 0xC3, 0xC4, 0x00];
+
+const HEADER_DECOMPRESS: u8 = 0x01;
+const HEADER_CODE_TABLE: u8 = 0x02;
+const HEADER_APPLICATION_DATA: u8 = 0x04;
+
+const WINDOW_SOURCE: u8 = 0x01;
+const WINDOW_TARGET: u8 = 0x02;
+const WINDOW_ADLER32: u8 = 0x04;
+
+/// Size of the windows of the patches we create. Decoders keep a whole
+/// window in memory, so it has to stay reasonably small.
+const WINDOW_SIZE_BYTES: u64 = 1 << 20;
+
+/// The entries of the default code table we use to create patches: an
+/// ADD and a COPY with address mode 0, both with the size stored
+/// separately.
+const ADD_INDEX: u8 = 1;
+const COPY_INDEX: u8 = 19;
+
+const NEAR_CACHE_SIZE: usize = 4;
+const SAME_CACHE_SIZE: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum InstructionType {
+    Noop,
+    Add,
+    Run,
+    Copy,
+}
+
+/// One half of an entry of the code table. A size of zero means that
+/// the size is stored in the instructions section.
+#[derive(Clone, Copy)]
+struct Instruction {
+    instruction_type: InstructionType,
+    size: u8,
+    mode: u8,
+}
+
+const NOOP: Instruction = Instruction {
+    instruction_type: InstructionType::Noop,
+    size: 0,
+    mode: 0,
+};
+
+const fn add(size: u8) -> Instruction {
+    return Instruction {
+        instruction_type: InstructionType::Add,
+        size,
+        mode: 0,
+    };
+}
+
+const fn copy(size: u8, mode: u8) -> Instruction {
+    return Instruction {
+        instruction_type: InstructionType::Copy,
+        size,
+        mode,
+    };
+}
+
+/// The default code table, as defined in section 5.6 of the RFC.
+const DEFAULT_CODE_TABLE: [(Instruction, Instruction); 256] = {
+    let mut table = [(NOOP, NOOP); 256];
+    let run = Instruction {
+        instruction_type: InstructionType::Run,
+        size: 0,
+        mode: 0,
+    };
+    table[0] = (run, NOOP);
+
+    let mut index = 1;
+    let mut size = 0;
+    while size <= 17 {
+        table[index] = (add(size), NOOP);
+        index += 1;
+        size += 1;
+    }
+
+    let mut mode = 0;
+    while mode <= 8 {
+        table[index] = (copy(0, mode), NOOP);
+        index += 1;
+        let mut size = 4;
+        while size <= 18 {
+            table[index] = (copy(size, mode), NOOP);
+            index += 1;
+            size += 1;
+        }
+        mode += 1;
+    }
+
+    let mut mode = 0;
+    while mode <= 8 {
+        let mut add_size = 1;
+        while add_size <= 4 {
+            if mode <= 5 {
+                let mut copy_size = 4;
+                while copy_size <= 6 {
+                    table[index] = (add(add_size), copy(copy_size, mode));
+                    index += 1;
+                    copy_size += 1;
+                }
+            }
+            else {
+                table[index] = (add(add_size), copy(4, mode));
+                index += 1;
+            }
+            add_size += 1;
+        }
+        mode += 1;
+    }
+
+    let mut mode = 0;
+    while mode <= 8 {
+        table[index] = (copy(4, mode), add(1));
+        index += 1;
+        mode += 1;
+    }
+
+    assert!(index == 256);
+    table
+};
+
+fn encode_number(output: &mut Vec<u8>, mut number: u64) {
+    let mut bytes = vec![(number & 0x7F) as u8];
+    number >>= 7;
+    while number > 0 {
+        bytes.push(0x80 | (number & 0x7F) as u8);
+        number >>= 7;
+    }
+    bytes.reverse();
+    output.extend_from_slice(&bytes);
+}
+
+fn decode_number(data: &[u8], offset: &mut usize) -> u64 {
+    let mut number = 0;
+    loop {
+        let byte = data[*offset];
+        *offset += 1;
+        number = number << 7 | (byte & 0x7F) as u64;
+        if byte & 0x80 == 0 {
+            return number;
+        }
+    }
+}
+
+/// Encode a single window that produces `target_offset..target_end` of
+/// the target. The source segment is the same range of the source, cut
+/// at its end. Unchanged bytes are copied from the source segment, and
+/// the changes that fall inside the window are added.
+fn encode_window(
+    vcdiff: &mut Vec<u8>,
+    target_offset: u64,
+    target_end: u64,
+    source_size: u64,
+    changes: &[(u64, &[u8])],
+) {
+    let source_segment_size = source_size
+        .saturating_sub(target_offset)
+        .min(target_end - target_offset);
+
+    let mut data = Vec::new();
+    let mut instructions = Vec::new();
+    let mut addresses = Vec::new();
+
+    let mut position = target_offset;
+    for &(offset, bytes) in changes {
+        if offset > position {
+            instructions.push(COPY_INDEX);
+            encode_number(&mut instructions, offset - position);
+            encode_number(&mut addresses, position - target_offset);
+        }
+        instructions.push(ADD_INDEX);
+        encode_number(&mut instructions, bytes.len() as u64);
+        data.extend_from_slice(bytes);
+        position = offset + bytes.len() as u64;
+    }
+    if position < target_end {
+        instructions.push(COPY_INDEX);
+        encode_number(&mut instructions, target_end - position);
+        encode_number(&mut addresses, position - target_offset);
+    }
+
+    let mut delta = Vec::new();
+    encode_number(&mut delta, target_end - target_offset);
+    delta.push(0);
+    encode_number(&mut delta, data.len() as u64);
+    encode_number(&mut delta, instructions.len() as u64);
+    encode_number(&mut delta, addresses.len() as u64);
+    delta.extend_from_slice(&data);
+    delta.extend_from_slice(&instructions);
+    delta.extend_from_slice(&addresses);
+
+    if source_segment_size > 0 {
+        vcdiff.push(WINDOW_SOURCE);
+        encode_number(vcdiff, source_segment_size);
+        encode_number(vcdiff, target_offset);
+    }
+    else {
+        vcdiff.push(0);
+    }
+    encode_number(vcdiff, delta.len() as u64);
+    vcdiff.extend_from_slice(&delta);
+}
+
+/// Create a patch from the file underlying `overlay` to the file with
+/// every write made through `overlay`.
+pub fn create_patch<F: Read + Seek>(overlay: &mut Overlay<F>) -> Vec<u8> {
+    let source_size = overlay.base_size_bytes();
+    let target_size = overlay.size_bytes();
+    let changes = overlay.changes();
+
+    let mut vcdiff = SIGNATURE.to_vec();
+    vcdiff.push(0);
+
+    let mut window_offset = 0;
+    while window_offset < target_size {
+        let window_end = (window_offset + WINDOW_SIZE_BYTES).min(target_size);
+
+        // The parts of the changes that fall inside this window.
+        let window_changes = changes
+            .iter()
+            .filter(|change| {
+                change.offset < window_end && change.end() > window_offset
+            })
+            .map(|change| {
+                let start = change.offset.max(window_offset);
+                let end = change.end().min(window_end);
+                let bytes = &change.data[(start - change.offset) as usize..]
+                    [..(end - start) as usize];
+                (start, bytes)
+            })
+            .collect::<Vec<(u64, &[u8])>>();
+
+        encode_window(
+            &mut vcdiff,
+            window_offset,
+            window_end,
+            source_size,
+            &window_changes,
+        );
+        window_offset = window_end;
+    }
+
+    return vcdiff;
+}
+
+/// The caches of recent addresses that COPY instructions can refer to,
+/// see section 5.1 of the RFC.
+struct AddressCache {
+    near: [u64; NEAR_CACHE_SIZE],
+    next_slot: usize,
+    same: [u64; SAME_CACHE_SIZE * 256],
+}
+
+impl AddressCache {
+    fn new() -> AddressCache {
+        return AddressCache {
+            near: [0; NEAR_CACHE_SIZE],
+            next_slot: 0,
+            same: [0; SAME_CACHE_SIZE * 256],
+        };
+    }
+
+    fn decode(
+        &mut self,
+        mode: u8,
+        here: u64,
+        addresses: &[u8],
+        offset: &mut usize,
+    ) -> u64 {
+        let mode = mode as usize;
+        let address = if mode == 0 {
+            decode_number(addresses, offset)
+        }
+        else if mode == 1 {
+            here - decode_number(addresses, offset)
+        }
+        else if mode < 2 + NEAR_CACHE_SIZE {
+            self.near[mode - 2] + decode_number(addresses, offset)
+        }
+        else {
+            let byte = addresses[*offset] as usize;
+            *offset += 1;
+            self.same[(mode - 2 - NEAR_CACHE_SIZE) * 256 + byte]
+        };
+
+        self.near[self.next_slot] = address;
+        self.next_slot = (self.next_slot + 1) % NEAR_CACHE_SIZE;
+        self.same[address as usize % (SAME_CACHE_SIZE * 256)] = address;
+        return address;
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULO: u32 = 65521;
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for chunk in data.chunks(4096) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MODULO;
+        b %= MODULO;
+    }
+    return b << 16 | a;
+}
+
+fn read_range<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    size: u64,
+) -> Vec<u8> {
+    let mut data = vec![0; size as usize];
+    reader.seek(SeekFrom::Start(offset)).unwrap();
+    reader.read_exact(&mut data).unwrap();
+    return data;
+}
+
+/// Decode a single window, starting at `offset` of the patch, and
+/// return the part of the target it produces.
+fn decode_window<S: Read + Seek, T: Read + Write + Seek>(
+    vcdiff: &[u8],
+    offset: &mut usize,
+    source: &mut S,
+    target: &mut BufWriter<T>,
+) -> Vec<u8> {
+    let indicator = vcdiff[*offset];
+    *offset += 1;
+
+    let segment = if indicator & (WINDOW_SOURCE | WINDOW_TARGET) != 0 {
+        let size = decode_number(vcdiff, offset);
+        let position = decode_number(vcdiff, offset);
+        if indicator & WINDOW_SOURCE != 0 {
+            read_range(source, position, size)
+        }
+        else {
+            target.flush().unwrap();
+            let data = read_range(target.get_mut(), position, size);
+            target.get_mut().seek(SeekFrom::End(0)).unwrap();
+            data
+        }
+    }
+    else {
+        Vec::new()
+    };
+
+    let delta_size = decode_number(vcdiff, offset) as usize;
+    let delta_end = *offset + delta_size;
+    let window_size = decode_number(vcdiff, offset) as usize;
+    let delta_indicator = vcdiff[*offset];
+    *offset += 1;
+    assert!(
+        delta_indicator == 0,
+        "Compressed VCDIFF patches are not supported. Create the patch \
+         with `xdelta3 -S none`."
+    );
+
+    let data_size = decode_number(vcdiff, offset) as usize;
+    let instructions_size = decode_number(vcdiff, offset) as usize;
+    let addresses_size = decode_number(vcdiff, offset) as usize;
+    let checksum = if indicator & WINDOW_ADLER32 != 0 {
+        let checksum = u32::from_be_bytes(
+            vcdiff[*offset..*offset + 4].try_into().unwrap(),
+        );
+        *offset += 4;
+        Some(checksum)
+    }
+    else {
+        None
+    };
+
+    let data = &vcdiff[*offset..][..data_size];
+    let instructions = &vcdiff[*offset + data_size..][..instructions_size];
+    let addresses =
+        &vcdiff[*offset + data_size + instructions_size..][..addresses_size];
+    *offset = delta_end;
+
+    let mut window = Vec::with_capacity(window_size);
+    let mut cache = AddressCache::new();
+    let mut data_offset = 0;
+    let mut instructions_offset = 0;
+    let mut addresses_offset = 0;
+
+    while instructions_offset < instructions.len() {
+        let index = instructions[instructions_offset] as usize;
+        instructions_offset += 1;
+
+        let (first, second) = DEFAULT_CODE_TABLE[index];
+        for instruction in [first, second] {
+            if instruction.instruction_type == InstructionType::Noop {
+                continue;
+            }
+
+            let size = match instruction.size {
+                0 => decode_number(instructions, &mut instructions_offset),
+                size => size as u64,
+            } as usize;
+
+            match instruction.instruction_type {
+                InstructionType::Add => {
+                    window.extend_from_slice(&data[data_offset..][..size]);
+                    data_offset += size;
+                }
+                InstructionType::Run => {
+                    let byte = data[data_offset];
+                    data_offset += 1;
+                    window.resize(window.len() + size, byte);
+                }
+                InstructionType::Copy => {
+                    let here = (segment.len() + window.len()) as u64;
+                    let address = cache.decode(
+                        instruction.mode,
+                        here,
+                        addresses,
+                        &mut addresses_offset,
+                    ) as usize;
+
+                    // Addresses past the source segment refer to the
+                    // window itself, and may overlap the bytes being
+                    // produced, so they are copied one at a time.
+                    for i in address..address + size {
+                        let byte = match i.checked_sub(segment.len()) {
+                            None => segment[i],
+                            Some(window_index) => window[window_index],
+                        };
+                        window.push(byte);
+                    }
+                }
+                InstructionType::Noop => unreachable!(),
+            }
+        }
+    }
+    assert!(window.len() == window_size, "The patch is damaged");
+
+    if let Some(checksum) = checksum {
+        assert!(
+            adler32(&window) == checksum,
+            "The patched file doesn't match the patch"
+        );
+    }
+
+    return window;
+}
+
+/// Apply a patch to `source`, writing the result to `target`, which
+/// should be empty. Panics if the patch is damaged or uses features we
+/// don't support.
+pub fn apply_patch<S: Read + Seek, T: Read + Write + Seek>(
+    source: &mut S,
+    target: &mut T,
+    vcdiff: &[u8],
+) {
+    assert!(vcdiff.starts_with(SIGNATURE), "Not a VCDIFF patch");
+
+    let mut offset = SIGNATURE.len();
+    let header_indicator = vcdiff[offset];
+    offset += 1;
+    assert!(
+        header_indicator & HEADER_DECOMPRESS == 0,
+        "Compressed VCDIFF patches are not supported. Create the patch \
+         with `xdelta3 -S none`."
+    );
+    assert!(
+        header_indicator & HEADER_CODE_TABLE == 0,
+        "VCDIFF patches with a custom code table are not supported"
+    );
+    if header_indicator & HEADER_APPLICATION_DATA != 0 {
+        let size = decode_number(vcdiff, &mut offset) as usize;
+        offset += size;
+    }
+
+    let mut writer = BufWriter::new(target);
+    while offset < vcdiff.len() {
+        let window = decode_window(vcdiff, &mut offset, source, &mut writer);
+        writer.write_all(&window).unwrap();
+    }
+    writer.flush().unwrap();
+}
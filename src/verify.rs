@@ -0,0 +1,65 @@
+//! Check the integrity of every sector of the data track. A sector that
+//! fails these checks was either damaged in the source dump or written
+//! incorrectly by us, and either can keep the game from booting. See
+//! `sector::check_sector` for what is checked.
+
+use std::io::{Read, Seek};
+
+use crate::image::RomImage;
+use crate::iso9660;
+use crate::sector::{self, SectorError};
+
+/// A sector that failed at least one check.
+pub struct BadSector {
+    pub sector_index: usize,
+
+    pub errors: Vec<SectorError>,
+
+    /// Path of the file or directory that the sector belongs to, if
+    /// any. Sectors outside any file include the system area, the
+    /// volume descriptors and the path tables.
+    pub path: Option<String>,
+}
+
+/// Check every sector of the data track and return the ones with
+/// problems, in order.
+///
+/// Panics for iso images, see `RomImage::read_sector_as_stored`.
+pub fn verify_image<F: Read + Seek>(rom: &mut RomImage<F>) -> Vec<BadSector> {
+    let mut bad_sectors = Vec::new();
+
+    for sector_index in 0..rom.sector_count() {
+        let raw_sector = rom.read_sector_as_stored(sector_index);
+        let errors = sector::check_sector(&raw_sector, sector_index);
+        if !errors.is_empty() {
+            bad_sectors.push(BadSector {
+                sector_index,
+                errors,
+                path: None,
+            });
+        }
+    }
+
+    if bad_sectors.is_empty() {
+        return bad_sectors;
+    }
+
+    // Only look up the owners once we know there is something to
+    // report. The filesystem itself might be damaged, so we only trust
+    // it if the volume descriptor is intact.
+    if iso9660::has_primary_volume_descriptor(rom) {
+        let entries = iso9660::read_filesystem(rom);
+        for bad_sector in &mut bad_sectors {
+            bad_sector.path = entries
+                .iter()
+                .find(|entry| {
+                    (entry.extent_sector
+                        ..entry.extent_sector + entry.size_sectors())
+                        .contains(&bad_sector.sector_index)
+                })
+                .map(|entry| entry.path.clone());
+        }
+    }
+
+    return bad_sectors;
+}